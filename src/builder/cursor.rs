@@ -0,0 +1,90 @@
+use super::*;
+
+/// Cursor
+/// Points at a position inside of a block and inserts
+/// instructions there. Unlike the Builder it may be moved
+/// freely between blocks, including already terminated ones,
+/// so it does not guard against malformed blocks.
+pub struct Cursor<'a> {
+    pub(crate) unit: &'a mut Unit,
+    pub(crate) block: Block,
+    // index into the instruction list of the block
+    pub(crate) pos: usize,
+    // given to every instruction inserted
+    pub(crate) loc: Option<SourceLoc>,
+}
+
+impl Unit {
+    /// Creates a cursor at the bottom of the given block.
+    pub fn cursor(&mut self, block: Block) -> Cursor<'_> {
+        let pos = self.blocks[block].insts.len();
+        Cursor {
+            unit: self,
+            block,
+            pos,
+            loc: None,
+        }
+    }
+}
+
+impl<'a> Cursor<'a> {
+    /// The block the cursor currently inserts into.
+    pub fn block(&self) -> Block {
+        self.block
+    }
+    /// The instruction directly after the cursor, if any.
+    pub fn current(&self) -> Option<Instruction> {
+        self.unit.blocks[self.block].insts.get(self.pos).copied()
+    }
+    /// Moves to the start of a block.
+    pub fn goto_top(&mut self, block: Block) {
+        self.block = block;
+        self.pos = 0;
+    }
+    /// Moves to the end of a block, after its terminator.
+    pub fn goto_bottom(&mut self, block: Block) {
+        self.block = block;
+        self.pos = self.unit.blocks[block].insts.len();
+    }
+    /// Moves right in front of the terminator of a block,
+    /// or to its end if it is not terminated yet.
+    pub fn goto_terminator(&mut self, block: Block) {
+        self.block = block;
+        self.pos = self.unit.blocks[block].term_start(&self.unit.instructions);
+    }
+    /// Moves in front of an instruction.
+    /// Panics if the instruction was removed from its block.
+    pub fn goto_before(&mut self, inst: Instruction) {
+        self.block = self.unit.instructions[inst].block;
+        self.pos = self.position_of(inst);
+    }
+    /// Moves behind an instruction.
+    /// Panics if the instruction was removed from its block.
+    pub fn goto_after(&mut self, inst: Instruction) {
+        self.block = self.unit.instructions[inst].block;
+        self.pos = self.position_of(inst) + 1;
+    }
+    fn position_of(&self, inst: Instruction) -> usize {
+        self.unit.blocks[self.block]
+            .insts
+            .iter()
+            .position(|i| *i == inst)
+            .unwrap_or_else(|| panic!("Instruction {} is not part of {}!", inst, self.block))
+    }
+    /// Sets the source location of the instructions inserted from now on.
+    pub fn set_loc(&mut self, loc: Option<SourceLoc>) {
+        self.loc = loc;
+    }
+    pub(crate) fn insert(&mut self, kind: InstKind, typing: Type) -> Instruction {
+        let inst = self.unit.instructions.push(InstData {
+            block: self.block,
+            kind,
+            typing,
+            loc: self.loc,
+        });
+        self.unit.blocks[self.block].insts.insert(self.pos, inst);
+        self.unit.add_uses(inst);
+        self.pos += 1;
+        inst
+    }
+}
@@ -1,69 +1,103 @@
-use crate::builder::Builder;
-use crate::data::{InstData, InstKind, Instruction, Type};
+use crate::builder::{Builder, Cursor};
+use crate::data::{InstKind, Instruction, SourceLoc, Type, UnitId};
+
+impl<'a> Cursor<'a> {
+    pub fn fetch_arg(&mut self, index: usize) -> Instruction {
+        let s = self.unit.blocks[self.block].signature;
+        // out of range indices are reported by the type checker
+        let t = self.unit.signatures[s]
+            .get(index)
+            .copied()
+            .unwrap_or(Type::Void);
+        self.insert(InstKind::FetchArg(index), t)
+    }
+    pub fn iconst(&mut self, t: Type, number: isize) -> Instruction {
+        self.insert(InstKind::IConst(number), t)
+    }
+    pub fn less(&mut self, args: [Instruction; 2]) -> Instruction {
+        self.insert(InstKind::Less(args), Type::Int32)
+    }
+    pub fn more(&mut self, args: [Instruction; 2]) -> Instruction {
+        self.insert(InstKind::More(args), Type::Int32)
+    }
+    pub fn add(&mut self, args: [Instruction; 2]) -> Instruction {
+        let t = self
+            .unit
+            .instructions
+            .get(args[0])
+            .map_or(Type::Void, |i| i.typing);
+        self.insert(InstKind::Add(args), t)
+    }
+    pub fn sub(&mut self, args: [Instruction; 2]) -> Instruction {
+        let t = self
+            .unit
+            .instructions
+            .get(args[0])
+            .map_or(Type::Void, |i| i.typing);
+        self.insert(InstKind::Sub(args), t)
+    }
+    pub fn mul(&mut self, args: [Instruction; 2]) -> Instruction {
+        let t = self
+            .unit
+            .instructions
+            .get(args[0])
+            .map_or(Type::Void, |i| i.typing);
+        self.insert(InstKind::Mul(args), t)
+    }
+    pub fn shl(&mut self, args: [Instruction; 2]) -> Instruction {
+        let t = self
+            .unit
+            .instructions
+            .get(args[0])
+            .map_or(Type::Void, |i| i.typing);
+        self.insert(InstKind::Shl(args), t)
+    }
+    pub fn recurse(&mut self, args: &[Instruction]) -> Instruction {
+        let data = self.unit.data.push_slice(args);
+        // resolved by the type checker once the return signature is known
+        let t = self.unit.retsig.unwrap_or(Type::Void);
+        self.insert(InstKind::Recur(data), t)
+    }
+    /// Calls another unit of the module, which returns `ret`.
+    pub fn call(&mut self, unit: UnitId, ret: Type, args: &[Instruction]) -> Instruction {
+        let data = self.unit.data.push_slice(args);
+        self.insert(InstKind::Call(unit, data), ret)
+    }
+}
 
 impl<'a> Builder<'a> {
+    /// Sets the source location of the instructions inserted from now on.
+    pub fn set_loc(&mut self, loc: Option<SourceLoc>) {
+        self.cursor.set_loc(loc);
+    }
     pub fn fetch_arg(&mut self, index: usize) -> Instruction {
-        let s = self.handle.blocks[self.block.index].signature;
-        let t = self.handle.signatures[s][index];
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::FetchArg(index),
-            typing: t,
-        };
-        self.handle.instructions.push(inst)
+        self.cursor.fetch_arg(index)
     }
     pub fn iconst(&mut self, t: Type, number: isize) -> Instruction {
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::IConst(number),
-            typing: t,
-        };
-        self.handle.instructions.push(inst)
+        self.cursor.iconst(t, number)
     }
     pub fn less(&mut self, args: [Instruction; 2]) -> Instruction {
-        let t = self.handle.instructions[args[0]].typing;
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::Less(args),
-            typing: t,
-        };
-        self.handle.instructions.push(inst)
+        self.cursor.less(args)
     }
     pub fn more(&mut self, args: [Instruction; 2]) -> Instruction {
-        let t = self.handle.instructions[args[0]].typing;
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::More(args),
-            typing: t,
-        };
-        self.handle.instructions.push(inst)
+        self.cursor.more(args)
     }
     pub fn add(&mut self, args: [Instruction; 2]) -> Instruction {
-        let t = self.handle.instructions[args[0]].typing;
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::Add(args),
-            typing: t,
-        };
-        self.handle.instructions.push(inst)
+        self.cursor.add(args)
     }
     pub fn sub(&mut self, args: [Instruction; 2]) -> Instruction {
-        let t = self.handle.instructions[args[0]].typing;
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::Sub(args),
-            typing: t,
-        };
-        self.handle.instructions.push(inst)
+        self.cursor.sub(args)
+    }
+    pub fn mul(&mut self, args: [Instruction; 2]) -> Instruction {
+        self.cursor.mul(args)
+    }
+    pub fn shl(&mut self, args: [Instruction; 2]) -> Instruction {
+        self.cursor.shl(args)
     }
     pub fn recurse(&mut self, args: &[Instruction]) -> Instruction {
-        let data = self.handle.data.push_slice(args);
-        let t = self.handle.retsig.unwrap_or(Type::Void);
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::Recur(data),
-            typing: t,
-        };
-        self.handle.instructions.push(inst)
+        self.cursor.recurse(args)
+    }
+    pub fn call(&mut self, unit: UnitId, ret: Type, args: &[Instruction]) -> Instruction {
+        self.cursor.call(unit, ret, args)
     }
 }
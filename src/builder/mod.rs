@@ -1,16 +1,21 @@
 use crate::data::{
-    Block, BlockData, BlockHandle, InstData, InstKind, Instruction, SigSlice, TermData, Type, Unit,
+    Block, BlockData, BlockHandle, InstData, InstKind, Instruction, SigSlice, SourceLoc, TermData,
+    Type, Unit,
 };
-use crate::util::{False, True};
+use crate::passes::{Changes, PassManager};
+use crate::util::{False, True, TypeBool};
 use std::marker::PhantomData;
 
+mod cursor;
 mod instructions;
 mod terminators;
 
+pub use cursor::Cursor;
+
 /// Builder
 pub struct Builder<'a> {
     pub(crate) block: BlockHandle<False>,
-    pub(crate) handle: &'a mut Unit,
+    pub(crate) cursor: Cursor<'a>,
 }
 
 // Stuff related to the builder pattern used
@@ -24,6 +29,7 @@ impl Unit {
         let idx = self.blocks.next_idx();
         let sig_idx = self.signatures.push_slice(sig);
         self.blocks.push(BlockData::new(sig_idx));
+        self.invalidate(Changes::ALL);
         BlockHandle {
             index: idx,
             _p: PhantomData,
@@ -31,30 +37,32 @@ impl Unit {
     }
     /// Gives access to a block.
     /// It's used to insert instructions into a block.
-    /// Can only be used once and the closure needs
-    /// to return the updated form of the block
+    /// Can only be used once per handle and the closure
+    /// needs to return the updated form of the block
     /// obtained by inserting a terminator.
+    /// Use a `Cursor` to reopen the block afterwards.
     pub fn with_block<F>(&mut self, b: BlockHandle<False>, f: F) -> BlockHandle<True>
     where
         F: FnOnce(Builder) -> BlockHandle<True>,
     {
-        let idx = b.index;
-        self.blocks[idx].inst_range[0] = self.instructions.next_idx();
-        let out = f(Builder {
-            block: b,
-            handle: self,
-        });
-        self.blocks[idx].inst_range[1] = self.instructions.next_idx();
-        out
+        let cursor = self.cursor(b.index);
+        f(Builder { block: b, cursor })
     }
     /// Finalizes the unit.
-    /// Checks it for consistency
+    /// Checks it for consistency, then runs the
+    /// pipeline selected by `Settings::opt_level`
     pub fn finalize(mut self, sig: Type) -> Self {
         self.retsig = Some(sig);
         // run a function to check the validity of the ir here
+        if let Err(errors) = self.type_check() {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            panic!("Unit failed to type check:\n{}", errors.join("\n"));
+        }
         self.check_dependencies();
-        self.remove_unused();
-        self.annotate_liveness();
+        if self.settings.volatile {
+            self.check_volatile();
+        }
+        PassManager::preset(self.settings.opt_level).run(&mut self);
         self
     }
 }
@@ -1,24 +1,35 @@
 use super::*;
 
+/// Terminators for the Cursor
+/// An if is built by inserting `do_if` followed
+/// by the branches for the true and false case.
+impl<'a> Cursor<'a> {
+    pub fn ret(&mut self, args: &[Instruction]) -> Instruction {
+        self.branch(Block::MAX, args)
+    }
+    pub fn branch(&mut self, block: Block, args: &[Instruction]) -> Instruction {
+        let data = self.unit.data.push_slice(args);
+        self.insert(
+            InstKind::Terminator(TermData::Branch(block, data)),
+            Type::Void,
+        )
+    }
+    pub fn do_if(&mut self, condition: Instruction) -> Instruction {
+        self.insert(InstKind::Terminator(TermData::DoIf(condition)), Type::Void)
+    }
+}
+
 /// Terminators for the Builder
 impl<'a> Builder<'a> {
     pub(crate) fn ret_inner(&mut self, args: &[Instruction]) {
-        let data = self.handle.data.push_slice(args);
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::Terminator(TermData::Branch(Block::MAX, data)),
-            typing: Type::Void,
-        };
-        self.handle.instructions.push(inst);
+        self.cursor.ret(args);
     }
-    pub(crate) fn branch_inner<T>(&mut self, block: &BlockHandle<T>, args: &[Instruction]) {
-        let data = self.handle.data.push_slice(args);
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::Terminator(TermData::Branch(block.index, data)),
-            typing: Type::Void,
-        };
-        self.handle.instructions.push(inst);
+    pub(crate) fn branch_inner<T: TypeBool>(
+        &mut self,
+        block: &BlockHandle<T>,
+        args: &[Instruction],
+    ) {
+        self.cursor.branch(block.index, args);
     }
     pub(crate) fn terminate(self) -> BlockHandle<True> {
         let index = self.block.index;
@@ -31,17 +42,16 @@ impl<'a> Builder<'a> {
         self.ret_inner(args);
         self.terminate()
     }
-    pub fn branch<T>(mut self, block: &BlockHandle<T>, args: &[Instruction]) -> BlockHandle<True> {
+    pub fn branch<T: TypeBool>(
+        mut self,
+        block: &BlockHandle<T>,
+        args: &[Instruction],
+    ) -> BlockHandle<True> {
         self.branch_inner(block, args);
         self.terminate()
     }
-    pub fn do_if(self, condition: Instruction) -> IfBuilder<'a, False> {
-        let inst = InstData {
-            block: self.block.index,
-            kind: InstKind::Terminator(TermData::DoIf(condition)),
-            typing: Type::Void,
-        };
-        self.handle.instructions.push(inst);
+    pub fn do_if(mut self, condition: Instruction) -> IfBuilder<'a, False> {
+        self.cursor.do_if(condition);
         IfBuilder {
             builder: self,
             _p: PhantomData,
@@ -64,7 +74,7 @@ impl<'a> IfBuilder<'a, False> {
         self.builder.ret_inner(args);
         self.next()
     }
-    pub fn branch<T>(
+    pub fn branch<T: TypeBool>(
         mut self,
         block: &BlockHandle<T>,
         args: &[Instruction],
@@ -84,7 +94,11 @@ impl<'a> IfBuilder<'a, True> {
     pub fn ret(self, args: &[Instruction]) -> BlockHandle<True> {
         self.builder.ret(args)
     }
-    pub fn branch<T>(self, block: &BlockHandle<T>, args: &[Instruction]) -> BlockHandle<True> {
+    pub fn branch<T: TypeBool>(
+        self,
+        block: &BlockHandle<T>,
+        args: &[Instruction],
+    ) -> BlockHandle<True> {
         self.builder.branch(block, args)
     }
 }
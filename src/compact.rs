@@ -0,0 +1,69 @@
+use crate::data::{InstData, InstKind, Instruction, LiveData, Map, TermData, Unit};
+use crate::uses::Uses;
+use crate::util::KeyVec;
+
+impl Unit {
+    /// Drops tombstones and renumbers the remaining
+    /// instructions in order of their blocks.
+    /// Any `Instruction` obtained before is invalidated.
+    /// Returns whether any tombstones were dropped.
+    pub fn compact(&mut self) -> bool {
+        let mut order = Vec::new();
+        let mut map = Map::new();
+        for blockdata in self.blocks.iter() {
+            for &i in blockdata.insts.iter() {
+                if !matches!(self.instructions[i].kind, InstKind::Tombstone) {
+                    map.insert(i, Instruction(order.len() as u32));
+                    order.push(i);
+                }
+            }
+        }
+        if order.len() == self.instructions.len() {
+            return false;
+        }
+        let mut instructions = KeyVec::new();
+        let mut data = KeyVec::new();
+        for old in order {
+            let inst = &self.instructions[old];
+            let mut kind = inst.kind;
+            // operand lists are copied over, leaving
+            // behind the ones of dropped instructions
+            if let InstKind::Recur(d)
+            | InstKind::Call(_, d)
+            | InstKind::Terminator(TermData::Branch(_, d)) = &mut kind
+            {
+                *d = data.push_slice(&self.data[*d]);
+            }
+            for op in kind.get_insts_mut(&mut data) {
+                *op = map[op];
+            }
+            instructions.push(InstData {
+                block: inst.block,
+                typing: inst.typing,
+                kind,
+                loc: inst.loc,
+            });
+        }
+        for blockdata in self.blocks.iter_mut() {
+            blockdata.insts = blockdata
+                .insts
+                .iter()
+                .filter_map(|i| map.get(i).copied())
+                .collect();
+        }
+        self.liveness = std::mem::take(&mut self.liveness)
+            .into_iter()
+            .filter_map(|((b, i), l)| {
+                let l = match l {
+                    LiveData::Alive => LiveData::Alive,
+                    LiveData::Partial(u) => LiveData::Partial(*map.get(&u)?),
+                };
+                Some(((b, *map.get(&i)?), l))
+            })
+            .collect();
+        self.instructions = instructions;
+        self.data = data;
+        self.uses = Uses::compute(self);
+        true
+    }
+}
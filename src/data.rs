@@ -1,3 +1,5 @@
+use crate::passes::Analyses;
+use crate::uses::Uses;
 use crate::util::{Key, KeyVec};
 use std::marker::PhantomData;
 
@@ -6,22 +8,38 @@ pub type Map<K, V> = std::collections::BTreeMap<K, V>;
 
 // Contains structs that store the actual data
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     Int32,
     Void,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Settings {
     pub volatile: bool,
+    pub opt_level: OptLevel,
 }
 impl std::default::Default for Settings {
     fn default() -> Self {
-        Self { volatile: true }
+        Self {
+            volatile: true,
+            opt_level: OptLevel::O0,
+        }
     }
 }
 
+// Selects the pipeline run by `Unit::finalize`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+}
+
 // Containers
 //   ... contain data
 
@@ -36,37 +54,61 @@ pub struct Unit {
     pub(crate) instructions: KeyVec<Instruction, InstData>,
     pub liveness: Map<(Block, Instruction), LiveData>,
     pub(crate) retsig: Option<Type>,
+    // kept up to date by every insertion and removal
+    pub(crate) uses: Uses,
+    pub(crate) analyses: Analyses,
 }
 
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlockData {
     pub(crate) signature: [SignaturePart; 2],
-    pub(crate) inst_range: [Instruction; 2],
+    // instructions in order of execution, these
+    // need not be contiguous in `Unit::instructions`
+    pub(crate) insts: Vec<Instruction>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct InstData {
     pub(crate) block: Block,
     pub(crate) typing: Type,
     pub(crate) kind: InstKind,
+    pub(crate) loc: Option<SourceLoc>,
+}
+/// Where in the source an instruction comes from,
+/// the file is numbered however the frontend likes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceLoc {
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
 }
-pub(crate) enum InstKind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InstKind {
     Tombstone,
     FetchArg(usize),
     IConst(isize),
     Add([Instruction; 2]),
     Sub([Instruction; 2]),
+    Mul([Instruction; 2]),
+    Shl([Instruction; 2]),
     Less([Instruction; 2]),
     More([Instruction; 2]),
     Recur([DataPart; 2]),
+    Call(UnitId, [DataPart; 2]),
     Terminator(TermData),
 }
-pub(crate) enum TermData {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TermData {
     DoIf(Instruction),
     Branch(Block, [DataPart; 2]),
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub(crate) enum LiveData {
+pub enum LiveData {
     Alive,
     Partial(Instruction),
 }
@@ -76,16 +118,24 @@ pub(crate) enum LiveData {
 //   used for associating and addressing stuff
 
 // addresses extra data, index + length
-#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DataPart(pub(crate) u32);
 // addresses a signature, index + length
-#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SignaturePart(pub(crate) u32);
 // addresses an instruction, index only
-#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Instruction(pub(crate) u32);
-#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block(pub(crate) u32);
+// addresses a unit within a module
+#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Copy, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitId(pub(crate) u32);
 // stores some guards for builders
 pub struct BlockHandle<Init> {
     pub(crate) index: Block,
@@ -106,9 +156,16 @@ impl Unit {
             instructions: KeyVec::new(),
             liveness: Map::new(),
             retsig: None,
+            uses: Uses::default(),
+            analyses: Analyses::default(),
         }
     }
 }
+impl std::default::Default for Unit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl BlockData {
     pub(crate) fn new(sig: [SignaturePart; 2]) -> Self {
@@ -117,6 +174,14 @@ impl BlockData {
             ..Default::default()
         }
     }
+    // position of the first terminator in `insts`,
+    // or the length if the block is not terminated yet
+    pub(crate) fn term_start(&self, instructions: &KeyVec<Instruction, InstData>) -> usize {
+        self.insts
+            .iter()
+            .position(|i| matches!(instructions[*i].kind, InstKind::Terminator(_)))
+            .unwrap_or(self.insts.len())
+    }
 }
 
 impl Key for Instruction {
@@ -135,6 +200,11 @@ impl Key for Instruction {
 impl Block {
     pub const MAX: Self = Self(u32::MAX);
 }
+impl<Init> BlockHandle<Init> {
+    pub fn block(&self) -> Block {
+        self.index
+    }
+}
 impl Key for Block {
     fn from(index: usize) -> Option<Self>
     where
@@ -148,6 +218,19 @@ impl Key for Block {
     }
 }
 
+impl Key for UnitId {
+    fn from(index: usize) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        Some(Self(index.try_into().ok()?))
+    }
+
+    fn into(self) -> usize {
+        self.0 as usize
+    }
+}
+
 impl Key for SignaturePart {
     fn from(idx: usize) -> Option<Self>
     where
@@ -184,8 +267,26 @@ impl std::fmt::Debug for Instruction {
         write!(f, "@{}", self.0)
     }
 }
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int32 => write!(f, "int32"),
+            Type::Void => write!(f, "void"),
+        }
+    }
+}
+impl std::fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "f{}:{}:{}", self.file, self.line, self.column)
+    }
+}
 impl std::fmt::Display for Block {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "b{}", self.0)
     }
 }
+impl std::fmt::Display for UnitId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "u{}", self.0)
+    }
+}
@@ -0,0 +1,128 @@
+use crate::data::{Block, Map, Set, Unit};
+
+// Dominator tree
+//   Computed with the iterative algorithm by Cooper, Harvey and Kennedy
+//   over the blocks reachable from the entry block
+
+#[derive(Debug, Default)]
+pub struct Dominators {
+    // reachable blocks in reverse postorder, entry first
+    rpo: Vec<Block>,
+    idom: Map<Block, Block>,
+    children: Map<Block, Vec<Block>>,
+}
+
+impl Dominators {
+    pub fn compute(unit: &Unit) -> Self {
+        let rpo = unit.reverse_postorder();
+        let index: Map<Block, usize> = rpo.iter().enumerate().map(|(i, b)| (*b, i)).collect();
+        let preds = unit.predecessors();
+        let mut idom = Map::new();
+        if let Some(entry) = rpo.first() {
+            idom.insert(*entry, *entry);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in rpo.iter().skip(1) {
+                let mut new = None;
+                for p in preds.get(b).into_iter().flatten() {
+                    if !idom.contains_key(p) {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => *p,
+                        Some(n) => intersect(&idom, &index, *p, n),
+                    });
+                }
+                if let Some(new) = new {
+                    if idom.insert(*b, new) != Some(new) {
+                        changed = true;
+                    }
+                }
+            }
+        }
+        let mut children = Map::<Block, Vec<Block>>::new();
+        for b in rpo.iter().skip(1) {
+            children.entry(idom[b]).or_default().push(*b);
+        }
+        Self {
+            rpo,
+            idom,
+            children,
+        }
+    }
+    /// The immediate dominator, None for the entry and unreachable blocks.
+    pub fn idom(&self, block: Block) -> Option<Block> {
+        self.idom.get(&block).copied().filter(|d| *d != block)
+    }
+    /// Whether `a` dominates `b`, every block dominates itself.
+    pub fn dominates(&self, a: Block, mut b: Block) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom(b) {
+                Some(d) => b = d,
+                None => return false,
+            }
+        }
+    }
+    /// The blocks immediately dominated by the given one.
+    pub fn children(&self, block: Block) -> &[Block] {
+        self.children.get(&block).map_or(&[], |c| c)
+    }
+    pub fn is_reachable(&self, block: Block) -> bool {
+        self.idom.contains_key(&block)
+    }
+    /// Reachable blocks in reverse postorder.
+    pub fn reverse_postorder(&self) -> &[Block] {
+        &self.rpo
+    }
+}
+
+fn intersect(idom: &Map<Block, Block>, index: &Map<Block, usize>, a: Block, b: Block) -> Block {
+    let (mut a, mut b) = (a, b);
+    while a != b {
+        while index[&a] > index[&b] {
+            a = idom[&a];
+        }
+        while index[&b] > index[&a] {
+            b = idom[&b];
+        }
+    }
+    a
+}
+
+impl Unit {
+    pub(crate) fn reverse_postorder(&self) -> Vec<Block> {
+        let mut out = Vec::new();
+        if self.blocks.len() == 0 {
+            return out;
+        }
+        let mut seen = Set::new();
+        // explicit stack of blocks along with whether
+        // their successors were pushed already
+        let mut stack = vec![(Block(0), false)];
+        while let Some((b, done)) = stack.pop() {
+            if done {
+                out.push(b);
+                continue;
+            }
+            if !seen.insert(b) {
+                continue;
+            }
+            stack.push((b, true));
+            for n in self.successors(b).into_iter().rev() {
+                if !seen.contains(&n) {
+                    stack.push((n, false));
+                }
+            }
+        }
+        out.reverse();
+        out
+    }
+}
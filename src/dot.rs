@@ -0,0 +1,111 @@
+use crate::data::{Block, InstKind, LiveData, TermData, Unit};
+use crate::dominators::Dominators;
+use std::fmt::Write;
+
+// Graphviz export
+//   Renders the control flow graph in the DOT language,
+//   every block becomes a record listing its instructions
+//   and the condition of its if, branches become edges
+
+/// What to draw on top of the control flow graph.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DotOptions {
+    /// List the values alive at the end of each block.
+    pub liveness: bool,
+    /// Draw a dashed edge from the immediate dominator of each block to it.
+    pub dominators: bool,
+}
+
+// record labels give meaning to these characters
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if matches!(c, '{' | '}' | '|' | '<' | '>' | '"' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+impl Unit {
+    /// Renders the control flow graph for Graphviz.
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(DotOptions::default())
+    }
+    pub fn to_dot_with(&self, options: DotOptions) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph unit {{").unwrap();
+        writeln!(out, "\tnode [shape=record, fontname=monospace];").unwrap();
+        for (b, blockdata) in self.blocks.iter().enumerate() {
+            let block = Block(b as u32);
+            let mut label = escape(&self.block_header(block));
+            label.push('|');
+            for &i in blockdata.insts.iter() {
+                let inst = &self.instructions[i];
+                let text = match inst.kind {
+                    InstKind::Terminator(TermData::Branch(..)) => continue,
+                    InstKind::Terminator(TermData::DoIf(_)) => inst.human_format(self),
+                    _ => format!("{i} {}", inst.human_format(self)),
+                };
+                label.push_str(&escape(&text));
+                label.push_str("\\l");
+            }
+            if options.liveness {
+                let live: Vec<String> = self
+                    .liveness
+                    .iter()
+                    .filter(|((lb, _), d)| *lb == block && **d == LiveData::Alive)
+                    .map(|((_, i), _)| i.to_string())
+                    .collect();
+                label.push('|');
+                label.push_str(&escape(&format!("live out: {}", live.join(", "))));
+                label.push_str("\\l");
+            }
+            writeln!(out, "\t{block} [label=\"{{{label}}}\"];").unwrap();
+        }
+        let ret = self.return_header().unwrap_or_else(|| "return".into());
+        writeln!(out, "\tret [shape=oval, label=\"{ret}\"];").unwrap();
+        for (b, _) in self.blocks.iter().enumerate() {
+            let block = Block(b as u32);
+            let term = self.terminator(block);
+            let conditional = matches!(
+                term.first().map(|i| self.instructions[*i].kind),
+                Some(InstKind::Terminator(TermData::DoIf(_)))
+            );
+            let branches = term
+                .iter()
+                .filter_map(|i| match self.instructions[*i].kind {
+                    InstKind::Terminator(TermData::Branch(t, d)) => Some((t, d)),
+                    _ => None,
+                });
+            for (n, (target, d)) in branches.enumerate() {
+                let args = format!("{:?}", &self.data[d]);
+                let label = match (conditional, n) {
+                    (true, 0) => format!("true {args}"),
+                    (true, _) => format!("false {args}"),
+                    (false, _) => args,
+                };
+                let target = match target {
+                    Block::MAX => "ret".to_string(),
+                    t => t.to_string(),
+                };
+                writeln!(out, "\t{block} -> {target} [label=\"{label}\"];").unwrap();
+            }
+        }
+        if options.dominators {
+            let doms = Dominators::compute(self);
+            for &b in doms.reverse_postorder() {
+                if let Some(d) = doms.idom(b) {
+                    writeln!(
+                        out,
+                        "\t{d} -> {b} [style=dashed, color=gray, constraint=false];"
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
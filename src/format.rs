@@ -1,99 +1,227 @@
-use crate::data::{Block, InstData, InstKind, Instruction, TermData, Unit};
+use crate::data::{
+    Block, DataPart, InstData, InstKind, Instruction, LiveData, Map, TermData, Type, Unit,
+};
 
-pub(crate) struct InstIter([Instruction; 2]);
-impl std::iter::Iterator for InstIter {
-    type Item = Instruction;
+// Text format
+//   Every block is headed by its named parameters, terminators
+//   are prefixed by `:` and values by the name they define,
+//   followed by their source location as a comment if any.
+//   `human_format` names values by their `Instruction` while
+//   `canonical_format` numbers them in order of appearance
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.0[0] >= self.0[1] {
-            return None;
-        }
-        let out = self.0[0];
-        self.0[0].0 += 1;
-        Some(out)
+impl Unit {
+    /// Prints the unit, naming values by their `Instruction`.
+    pub fn human_format(&self) -> String {
+        self.write_text(&|i| i.to_string())
     }
-}
-impl std::iter::DoubleEndedIterator for InstIter {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if self.0[0] >= self.0[1] {
-            return None;
+    /// Prints the unit, numbering values in the order they are
+    /// defined in. Units built the same way print the same text,
+    /// no matter how their instructions are laid out in memory.
+    pub fn canonical_format(&self) -> String {
+        let mut names = Map::new();
+        for b in self.blocks.iter() {
+            for &i in b.insts.iter() {
+                if defines(&self.instructions[i].kind) {
+                    names.insert(i, names.len());
+                }
+            }
         }
-        let out = self.0[0];
-        self.0[1].0 -= 1;
-        Some(out)
-    }
-}
-impl Instruction {
-    pub(crate) fn until(self, end: Self) -> InstIter {
-        InstIter([self, end])
+        self.write_text(&|i| match names.get(&i) {
+            Some(n) => format!("@{n}"),
+            None => format!("@?{}", i.0),
+        })
     }
-}
-
-impl Unit {
-    pub fn human_format(&self) -> String {
+    fn write_text(&self, name: &dyn Fn(Instruction) -> String) -> String {
         use std::fmt::Write;
         let mut out: String = "".into();
-        let mut inst_counter = 0;
         for (bi, b) in self.blocks.iter().enumerate() {
-            write!(
-                out,
-                "---b{}{:?}:\n",
-                bi, &self.signatures[b.signature],
-            )
-            .unwrap();
-            for i in b.inst_range[0].until(b.inst_range[1]) {
-                write!(
-                    out,
-                    "|\t@{} {}\n",
-                    inst_counter,
-                    self.instructions[i].human_format(self)
-                )
-                .unwrap();
-                inst_counter += 1;
+            writeln!(out, "---{}:", self.block_header(Block(bi as u32))).unwrap();
+            for &i in b.insts.iter() {
+                let inst = &self.instructions[i];
+                let kind = &inst.kind;
+                if matches!(kind, InstKind::Tombstone) {
+                    continue;
+                }
+                let mut text = inst.format_with(self, name);
+                if defines(kind) {
+                    text = format!("{} {}", name(i), text);
+                }
+                match inst.loc {
+                    Some(loc) => writeln!(out, "|\t{text} // {loc}").unwrap(),
+                    None => writeln!(out, "|\t{text}").unwrap(),
+                }
             }
         }
-        if let Some(s) = &self.retsig {
-            write!(out, "---return({:?})\n", s).unwrap();
+        if let Some(ret) = self.return_header() {
+            writeln!(out, "---{ret}").unwrap();
         }
         out
     }
+    // a block along with its named parameters
+    pub(crate) fn block_header(&self, block: Block) -> String {
+        let params: Vec<String> = self.signatures[self.blocks[block].signature]
+            .iter()
+            .enumerate()
+            .map(|(p, t)| format!("p{p}: {t}"))
+            .collect();
+        format!("{block}({})", params.join(", "))
+    }
+    pub(crate) fn return_header(&self) -> Option<String> {
+        match self.retsig? {
+            Type::Void => Some("return()".into()),
+            t => Some(format!("return({t})")),
+        }
+    }
 }
 
-impl InstData {
-    fn human_format(&self, unit: &Unit) -> String {
-        self.kind.human_format(unit)
-    }
+fn defines(kind: &InstKind) -> bool {
+    !matches!(kind, InstKind::Terminator(_) | InstKind::Tombstone)
 }
 
-impl InstKind {
-    fn human_format(&self, unit: &Unit) -> String {
-        if let InstKind::Terminator(t) = self {
-            t.human_format(unit)
+impl InstData {
+    pub(crate) fn human_format(&self, unit: &Unit) -> String {
+        self.format_with(unit, &|i| i.to_string())
+    }
+    // prefixed by `:` for terminators and `=` for values
+    fn format_with(&self, unit: &Unit, name: &dyn Fn(Instruction) -> String) -> String {
+        let sep = if matches!(self.kind, InstKind::Terminator(_)) {
+            ':'
         } else {
-            format!(
-                "= {}",
-                &match self {
-                    InstKind::Tombstone => "_".to_string(),
-                    InstKind::FetchArg(i) => format!("fetchArg [{i}]"),
-                    InstKind::IConst(i) => format!("const {i}"),
-                    InstKind::Add([a, b]) => format!("add {a}, {b}"),
-                    InstKind::Sub([a, b]) => format!("sub {a}, {b}"),
-                    InstKind::Less([a, b]) => format!("less {a}, {b}"),
-                    InstKind::More([a, b]) => format!("more {a}, {b}"),
-                    InstKind::Recur(d) => format!("recur {:?}", &unit.data[*d]),
-                    InstKind::Terminator(_) => unreachable!(),
-                }
-            )
+            '='
+        };
+        format!("{sep} {}", self.format_op(unit, name))
+    }
+    // what the instruction does, like `add @1, @2`,
+    // calls name their type as the callee is not known
+    fn format_op(&self, unit: &Unit, name: &dyn Fn(Instruction) -> String) -> String {
+        let list = |d: [DataPart; 2]| {
+            let names: Vec<String> = unit.data[d].iter().map(|i| name(*i)).collect();
+            format!("[{}]", names.join(", "))
+        };
+        let bin = |op: &str, [a, b]: [Instruction; 2]| format!("{op} {}, {}", name(a), name(b));
+        match self.kind {
+            InstKind::Tombstone => "_".to_string(),
+            InstKind::FetchArg(i) => format!("fetchArg p{i}"),
+            InstKind::IConst(i) => format!("const {i}"),
+            InstKind::Add(a) => bin("add", a),
+            InstKind::Sub(a) => bin("sub", a),
+            InstKind::Mul(a) => bin("mul", a),
+            InstKind::Shl(a) => bin("shl", a),
+            InstKind::Less(a) => bin("less", a),
+            InstKind::More(a) => bin("more", a),
+            InstKind::Recur(d) => format!("recur {}", list(d)),
+            InstKind::Call(u, d) => format!("call {} {u} {}", self.typing, list(d)),
+            InstKind::Terminator(TermData::DoIf(c)) => format!("if {}", name(c)),
+            InstKind::Terminator(TermData::Branch(Block::MAX, a)) => format!("ret {}", list(a)),
+            InstKind::Terminator(TermData::Branch(b, a)) => format!("br {b} {}", list(a)),
         }
     }
 }
 
-impl TermData {
-    fn human_format(&self, unit: &Unit) -> String {
-        match self {
-            TermData::DoIf(c) => format!(": if {c}"),
-            TermData::Branch(Block::MAX, a) => format!(": ret {:?}", &unit.data[*a]),
-            TermData::Branch(b, a) => format!(": br {b} {:?}", &unit.data[*a]),
+// Lifetime tables
+//   Lists the instructions of every block next to the lifetimes
+//   of the values, a value is started with `+` where it is defined,
+//   continued with `|` while it lives and ended with `x` at its
+//   last use, which is also marked by a `once` row. Columns are
+//   reused once their value died, much like registers
+
+// a row of the table, `None` for the line between blocks
+type Row = Option<[String; 4]>;
+
+impl Unit {
+    /// Renders `Unit::liveness` as a lifetime table,
+    /// which needs to be up to date as after `finalize`.
+    pub fn lifetime_table(&self) -> String {
+        self.lifetime_table_with(&Map::new())
+    }
+    /// Same as `lifetime_table`, naming values by the
+    /// virtual register they are assigned to.
+    pub fn lifetime_table_with(&self, vregs: &Map<Instruction, u32>) -> String {
+        use std::fmt::Write;
+        let name = |i: &Instruction| vregs.get(i).map_or(i.to_string(), |v| format!("v{v}"));
+        let width = self.instructions.len().to_string().len() + 2;
+        let mut rows: Vec<Row> = Vec::new();
+        for (b, blockdata) in self.blocks.iter().enumerate() {
+            let block = Block(b as u32);
+            let mut slots: Vec<Option<Instruction>> = Vec::new();
+            // values coming from other blocks are alive from the start
+            for (_, i) in self
+                .liveness
+                .keys()
+                .filter(|(lb, i)| *lb == block && self.instructions[*i].block != block)
+            {
+                slots.push(Some(*i));
+            }
+            let cells = |slots: &[Option<Instruction>], event: Option<(Instruction, String)>| {
+                let mut out = String::new();
+                for s in slots {
+                    let cell = match (s, &event) {
+                        (Some(s), Some((e, text))) if s == e => text.clone(),
+                        (Some(_), _) => " |".into(),
+                        (None, _) => String::new(),
+                    };
+                    write!(out, " {cell:width$}").unwrap();
+                }
+                out
+            };
+            let mut label = block.to_string();
+            for &i in blockdata.insts.iter() {
+                let mut ops = self.instructions[i].kind.get_insts(&self.data).to_vec();
+                ops.sort();
+                ops.dedup();
+                for u in ops {
+                    if self.liveness.get(&(block, u)) != Some(&LiveData::Partial(i)) {
+                        continue;
+                    }
+                    let lifetimes = cells(&slots, Some((u, " x".into())));
+                    rows.push(Some([
+                        std::mem::take(&mut label),
+                        "___".into(),
+                        format!("once {}", name(&u)),
+                        lifetimes,
+                    ]));
+                    for s in slots.iter_mut().filter(|s| **s == Some(u)) {
+                        *s = None;
+                    }
+                }
+                let inst = &self.instructions[i];
+                let named = defines(&inst.kind);
+                // values without any use never get a column
+                let lives = self.liveness.contains_key(&(block, i));
+                if named && lives {
+                    match slots.iter().position(|s| s.is_none()) {
+                        Some(p) => slots[p] = Some(i),
+                        None => slots.push(Some(i)),
+                    }
+                }
+                let lifetimes = cells(&slots, Some((i, format!("+{}", name(&i)))));
+                rows.push(Some([
+                    std::mem::take(&mut label),
+                    if named { name(&i) } else { String::new() },
+                    inst.format_op(self, &|i| name(&i)),
+                    lifetimes,
+                ]));
+            }
+            rows.push(None);
         }
+        let w: Vec<usize> = (0..3)
+            .map(|c| rows.iter().flatten().map(|r| r[c].len()).max().unwrap_or(0))
+            .collect();
+        let mut out = String::new();
+        for row in rows {
+            let Some([block, id, text, lifetimes]) = row else {
+                let line: Vec<String> = w.iter().map(|w| "-".repeat(w + 2)).collect();
+                writeln!(out, "|{}|", line.join("+")).unwrap();
+                continue;
+            };
+            let line = format!(
+                "| {block:w0$} | {id:w1$} | {text:w2$} |{lifetimes}",
+                w0 = w[0],
+                w1 = w[1],
+                w2 = w[2]
+            );
+            writeln!(out, "{}", line.trim_end()).unwrap();
+        }
+        out
     }
 }
@@ -0,0 +1,141 @@
+use crate::data::{Block, InstKind, Instruction, TermData, Unit, UnitId};
+use crate::module::Module;
+use crate::passes::const_fold::eval;
+
+// Interpreter
+//   Runs a unit directly on the IR, values are Int32 and wrap
+//   around like the folding passes assume. Recursion and calls
+//   recurse in the interpreter as well, so their depth is limited
+
+/// How deep recursion and calls may nest.
+pub const MAX_DEPTH: usize = 1000;
+
+/// Why running a unit failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /// The unit was given the wrong amount of arguments.
+    ArgCount { expected: usize, found: usize },
+    /// A call refers to a unit that is not part of the module,
+    /// or the unit was run on its own.
+    UnknownUnit(UnitId),
+    /// Recursion or calls nested deeper than `MAX_DEPTH`.
+    StackOverflow,
+    /// A block fetches a parameter it was not passed,
+    /// which `Unit::finalize` rules out.
+    ArgIndex { block: Block, index: usize },
+    /// A block ends without a branch, or there is no entry
+    /// block, which `Unit::finalize` rules out.
+    Unterminated(Block),
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::ArgCount { expected, found } => {
+                write!(f, "expected {expected} arguments, found {found}")
+            }
+            RunError::UnknownUnit(u) => write!(f, "unit {u} does not exist"),
+            RunError::StackOverflow => write!(f, "recursion deeper than {MAX_DEPTH}"),
+            RunError::ArgIndex { block, index } => write!(f, "{block} has no parameter p{index}"),
+            RunError::Unterminated(b) => write!(f, "{b} is not terminated"),
+        }
+    }
+}
+impl std::error::Error for RunError {}
+
+impl Unit {
+    /// Runs a finalized unit on the given arguments, returning
+    /// its result or `None` if it returns nothing.
+    pub fn run(&self, args: &[isize]) -> Result<Option<isize>, RunError> {
+        self.run_in(None, args, 0)
+    }
+    fn run_in(
+        &self,
+        module: Option<&Module>,
+        args: &[isize],
+        depth: usize,
+    ) -> Result<Option<isize>, RunError> {
+        if depth > MAX_DEPTH {
+            return Err(RunError::StackOverflow);
+        }
+        let entry = self
+            .blocks
+            .get(Block(0))
+            .ok_or(RunError::Unterminated(Block(0)))?;
+        let params = self.signatures[entry.signature].len();
+        if args.len() != params {
+            return Err(RunError::ArgCount {
+                expected: params,
+                found: args.len(),
+            });
+        }
+        let mut values = vec![0; self.instructions.len()];
+        let mut block = Block(0);
+        let mut args: Vec<isize> = args.iter().map(|a| *a as i32 as isize).collect();
+        loop {
+            let mut next = None;
+            // set by an if, which skips the branch not taken
+            let mut skip = None;
+            for &i in self.blocks[block].insts.iter() {
+                let get =
+                    |v: &[Instruction]| v.iter().map(|x| values[x.0 as usize]).collect::<Vec<_>>();
+                let value = match self.instructions[i].kind {
+                    InstKind::Tombstone => continue,
+                    InstKind::FetchArg(n) => {
+                        *args.get(n).ok_or(RunError::ArgIndex { block, index: n })?
+                    }
+                    InstKind::IConst(n) => n as i32 as isize,
+                    ref kind @ (InstKind::Add([a, b])
+                    | InstKind::Sub([a, b])
+                    | InstKind::Mul([a, b])
+                    | InstKind::Shl([a, b])
+                    | InstKind::Less([a, b])
+                    | InstKind::More([a, b])) => {
+                        eval(kind, values[a.0 as usize], values[b.0 as usize]).unwrap()
+                    }
+                    InstKind::Recur(d) => {
+                        let args = get(&self.data[d]);
+                        self.run_in(module, &args, depth + 1)?.unwrap_or(0)
+                    }
+                    InstKind::Call(u, d) => {
+                        let callee = module
+                            .and_then(|m| m.get(u))
+                            .ok_or(RunError::UnknownUnit(u))?;
+                        let args = get(&self.data[d]);
+                        callee.run_in(module, &args, depth + 1)?.unwrap_or(0)
+                    }
+                    InstKind::Terminator(TermData::DoIf(c)) => {
+                        skip = Some(values[c.0 as usize] == 0);
+                        continue;
+                    }
+                    InstKind::Terminator(TermData::Branch(b, d)) => {
+                        // the first branch is taken if the condition holds
+                        match skip {
+                            Some(true) => skip = Some(false),
+                            _ => {
+                                next = Some((b, get(&self.data[d])));
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                };
+                values[i.0 as usize] = value;
+            }
+            let (b, out) = next.ok_or(RunError::Unterminated(block))?;
+            if b == Block::MAX {
+                return Ok(out.first().copied());
+            }
+            block = b;
+            args = out;
+        }
+    }
+}
+
+impl Module {
+    /// Runs a unit of the module, which may call other units.
+    pub fn run(&self, id: UnitId, args: &[isize]) -> Result<Option<isize>, RunError> {
+        let unit = self.get(id).ok_or(RunError::UnknownUnit(id))?;
+        unit.run_in(Some(self), args, 0)
+    }
+}
@@ -1,11 +1,52 @@
 mod builder;
+mod compact;
 mod data;
+mod dominators;
+mod dot;
 mod format;
+mod interp;
+mod loops;
+mod module;
+mod mutate;
+mod parse;
+mod passes;
+mod repl;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod serialize;
+mod typecheck;
+mod uses;
 mod util;
 mod verification;
 
+pub use builder::Cursor;
+pub use data::Block;
+pub use data::DataPart;
+pub use data::InstKind;
+pub use data::Instruction;
+pub use data::OptLevel;
+pub use data::Settings;
+pub use data::SourceLoc;
+pub use data::TermData;
 pub use data::Type;
 pub use data::Unit;
+pub use data::UnitId;
+pub use dominators::Dominators;
+pub use dot::DotOptions;
+pub use interp::{RunError, MAX_DEPTH};
+pub use loops::{Loop, Loops};
+pub use module::Module;
+pub use mutate::{Target, Terminator};
+pub use parse::ParseError;
+pub use passes::peephole;
+pub use passes::{
+    Analyses, Changes, Compact, ConstFold, DeadParams, Dominance, Gvn, Licm, Liveness, Pass,
+    PassManager, Peephole, RemoveUnused, Sccp, SimplifyCfg, TailRecursion,
+};
+pub use repl::Repl;
+pub use serialize::{DecodeError, FORMAT_VERSION};
+pub use typecheck::{TypeError, TypeErrorKind};
+pub use uses::{Use, Uses};
 
 #[cfg(test)]
 mod tests {
@@ -35,7 +76,26 @@ mod tests {
         });
         let unit = unit.finalize(Type::Int32);
         eprintln!("{}", unit.human_format());
-        eprintln!("--- liveness ---\n{:#?}", unit.liveness);
+        let table = unit.lifetime_table();
+        eprintln!("{table}");
+        assert!(table.contains("| ___ | once @1"));
+        // @0 is defined in b0 and keeps its column until its last use in b1
+        assert!(table.contains(concat!(
+            "| b1 | @6  | const 1     |  |   +@6\n",
+            "|    | ___ | once @6     |  |    x\n",
+            "|    | @7  | sub @0, @6  |  |   +@7\n",
+            "|    | ___ | once @7     |  |    x\n",
+            "|    | @8  | recur [@7]  |  |   +@8\n",
+            "|    | @9  | const 2     |  |    |   +@9\n",
+            "|    | ___ | once @0     |  x    |    |\n",
+        )));
+        // subtracting from @0 in b1 can take over its register only at its last use
+        let vregs = unit.assign_vregs();
+        eprintln!("{}", unit.lifetime_table_with(&vregs));
+        let reg = |i| vregs[&Instruction(i)];
+        assert_ne!(reg(7), reg(0));
+        assert_eq!(reg(10), reg(0));
+        assert_eq!(reg(12), reg(8));
     }
     #[test]
     fn construct() {
@@ -80,8 +140,1010 @@ mod tests {
             let d = block.add([a, b]);
             block.ret(&[d])
         });
-        let unit = unit.finalize(Type::Void);
+        let unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+    }
+    #[test]
+    fn interleaved() {
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32]).block();
+        let b1 = unit.new_block(&[]).block();
+        let b2 = unit.new_block(&[]).block();
+        let mut cursor = unit.cursor(b0);
+        let n = cursor.fetch_arg(0);
+        let c = cursor.less([n, n]);
+        let term = [
+            cursor.do_if(c),
+            cursor.branch(b1, &[]),
+            cursor.branch(b2, &[]),
+        ];
+        // emit into both branches alternately
+        cursor.goto_bottom(b1);
+        let x = cursor.iconst(Type::Int32, 1);
+        cursor.goto_bottom(b2);
+        let y = cursor.iconst(Type::Int32, 2);
+        cursor.goto_bottom(b1);
+        cursor.ret(&[x]);
+        cursor.goto_bottom(b2);
+        let z = cursor.add([y, n]);
+        let ret = cursor.ret(&[z]);
+        // reopen the entry block in front of its terminator
+        cursor.goto_terminator(b0);
+        let one = cursor.iconst(Type::Int32, 1);
+        cursor.goto_before(z);
+        let w = cursor.add([y, one]);
+        assert_eq!(cursor.current(), Some(z));
+        assert_eq!(unit.blocks[b0].insts, [&[n, c, one][..], &term].concat());
+        assert_eq!(unit.blocks[b2].insts, vec![y, w, z, ret]);
+        unit.settings.volatile = false;
+        let unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        // values are numbered in block order, not by where they were created
+        assert_eq!(
+            unit.canonical_format(),
+            "---b0(p0: int32):\n|\t@0 = fetchArg p0\n|\t@1 = less @0, @0\n\
+             |\t: if @1\n|\t: br b1 []\n|\t: br b2 []\n\
+             ---b1():\n|\t@2 = const 1\n|\t: ret [@2]\n\
+             ---b2():\n|\t@3 = const 2\n|\t@4 = add @3, @0\n|\t: ret [@4]\n\
+             ---return(int32)\n"
+        );
+    }
+    #[test]
+    fn mutation() {
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[]);
+        let (mut a, mut b, mut d) = Default::default();
+        unit.with_block(b0, |mut block| {
+            a = block.iconst(Type::Int32, 5);
+            b = block.iconst(Type::Int32, 10);
+            d = block.add([a, b]);
+            block.ret(&[d])
+        });
+        let c = unit.insert_before(d, InstKind::IConst(15), Type::Int32);
+        unit.update_liveness();
+        unit.replace_all_uses(d, c);
+        assert!(!unit.analyses.liveness);
+        assert_eq!(unit.users(d).count(), 0);
+        unit.remove(d);
+        unit.remove(a);
+        unit.remove(b);
+        let ret = unit.users(c).next().unwrap();
+        let n = unit.insert_before(ret, InstKind::IConst(1), Type::Int32);
+        let m = unit.insert_before(ret, InstKind::Sub([c, n]), Type::Int32);
+        unit.set_terminator(Block(0), Terminator::Branch(Target(Block::MAX, &[m])));
+        assert_eq!(unit.users(c).collect::<Vec<_>>(), vec![m]);
+        assert_eq!(unit.uses(), &Uses::compute(&unit));
+        let unit = unit.finalize(Type::Int32);
+        assert_eq!(unit.blocks[Block(0)].insts.len(), 4);
+        eprintln!("{}", unit.human_format());
+    }
+    #[test]
+    fn uses() {
+        let mut unit = Unit::new();
+        unit.settings.volatile = false;
+        let b0 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let (mut a, mut d, mut r) = Default::default();
+        unit.with_block(b0, |mut block| {
+            a = block.iconst(Type::Int32, 5);
+            let _ = block.iconst(Type::Int32, 7);
+            d = block.sub([a, a]);
+            r = block.recurse(&[d, a]);
+            block.ret(&[r])
+        });
+        let found: Vec<_> = unit.uses().of(a).map(|u| (u.user, u.operand)).collect();
+        assert_eq!(found, vec![(d, 0), (d, 1), (r, 1)]);
+        assert_eq!(unit.users(a).collect::<Vec<_>>(), vec![d, r]);
+        let unit = unit.finalize(Type::Int32);
+        assert_eq!(unit.uses(), &Uses::compute(&unit));
+    }
+    #[test]
+    fn compaction() {
+        let mut unit = Unit::new();
+        unit.settings.volatile = false;
+        let b0 = unit.new_block(&[]);
+        unit.with_block(b0, |mut block| {
+            let _ = block.iconst(Type::Int32, 1);
+            let a = block.iconst(Type::Int32, 5);
+            let _ = block.iconst(Type::Int32, 0);
+            let b = block.iconst(Type::Int32, 10);
+            let d = block.add([a, b]);
+            block.ret(&[d])
+        });
+        unit.remove_unused();
+        unit.annotate_liveness();
+        unit.compact();
+        let [a, b, d, r] = [0, 1, 2, 3].map(Instruction);
+        assert_eq!(unit.instructions.len(), 4);
+        assert_eq!(unit.blocks[Block(0)].insts, vec![a, b, d, r]);
+        assert_eq!(unit.instructions[d].kind, InstKind::Add([a, b]));
+        assert_eq!(unit.instructions[r].kind.get_insts(&unit.data), &[d]);
+        assert_eq!(unit.liveness[&(Block(0), a)], data::LiveData::Partial(d));
+        assert_eq!(unit.liveness[&(Block(0), d)], data::LiveData::Partial(r));
+        assert_eq!(unit.uses(), &Uses::compute(&unit));
+    }
+    #[test]
+    fn pass_manager() {
+        struct Touch(Changes);
+        impl Pass for Touch {
+            fn name(&self) -> &'static str {
+                "touch"
+            }
+            fn run(&mut self, _: &mut Unit) -> Changes {
+                self.0
+            }
+        }
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[]);
+        let b1 = unit.new_block(&[]);
+        let b2 = unit.new_block(&[]);
+        unit.with_block(b0, |mut block| {
+            let c = block.iconst(Type::Int32, 1);
+            block.do_if(c).branch(&b1, &[]).branch(&b2, &[])
+        });
+        unit.with_block(b1, |block| block.branch(&b2, &[]));
+        unit.with_block(b2, |block| block.ret(&[]));
+        let mut pm = PassManager::new();
+        pm.add(Dominance).add(Liveness).add(Touch(Changes::INSTS));
+        assert_eq!(pm.run(&mut unit), Changes::INSTS);
+        assert!(unit.analyses.dominators.is_some());
+        assert!(!unit.analyses.liveness);
+        let doms = unit.dominators();
+        assert_eq!(doms.idom(Block(2)), Some(Block(0)));
+        assert_eq!(doms.idom(Block(1)), Some(Block(0)));
+        assert!(!doms.dominates(Block(1), Block(2)));
+        let mut pm = PassManager::new();
+        pm.add(Liveness).add(Touch(Changes::ALL));
+        pm.run(&mut unit);
+        assert!(unit.analyses.dominators.is_none());
+        // inserting a branch changes the graph as well
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[]).block();
+        let b1 = unit.new_block(&[]).block();
+        unit.cursor(b1).ret(&[]);
+        assert!(!unit.dominators().dominates(b0, b1));
+        unit.cursor(b0).branch(b1, &[]);
+        assert!(unit.dominators().dominates(b0, b1));
+        assert_eq!(
+            PassManager::preset(OptLevel::O0)
+                .names()
+                .collect::<Vec<_>>(),
+            vec!["remove-unused", "compact", "liveness"]
+        );
+    }
+    #[test]
+    fn const_fold() {
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O1;
+        let b0 = unit.new_block(&[Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            let zero = block.iconst(Type::Int32, 0);
+            let max = block.iconst(Type::Int32, i32::MAX as isize);
+            let one = block.iconst(Type::Int32, 1);
+            let wrapped = block.add([max, one]);
+            let m = block.add([zero, n]);
+            let k = block.sub([m, zero]);
+            let c = block.less([wrapped, zero]);
+            block.do_if(c).branch(&b1, &[k]).ret(&[wrapped])
+        });
+        unit.with_block(b1, |mut block| {
+            let n = block.fetch_arg(0);
+            let z = block.sub([n, n]);
+            block.ret(&[z])
+        });
+        let unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        // the if folds into a branch to b1, which is merged into b0
+        let b0: Vec<_> = unit.blocks[Block(0)]
+            .insts
+            .iter()
+            .map(|i| unit.instructions[*i].kind)
+            .collect();
+        assert_eq!(unit.blocks.len(), 1);
+        assert_eq!(b0.len(), 2);
+        assert_eq!(b0[0], InstKind::IConst(0));
+        assert!(matches!(
+            b0[1],
+            InstKind::Terminator(TermData::Branch(Block::MAX, _))
+        ));
+    }
+    #[test]
+    fn sccp() {
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O2;
+        let b0 = unit.new_block(&[]);
+        let b1 = unit.new_block(&[Type::Int32]);
+        let b2 = unit.new_block(&[Type::Int32]);
+        let b3 = unit.new_block(&[Type::Int32]);
+        let b4 = unit.new_block(&[]);
+        unit.with_block(b0, |mut block| {
+            let c = block.iconst(Type::Int32, 3);
+            block.branch(&b1, &[c])
+        });
+        unit.with_block(b1, |mut block| {
+            let n = block.fetch_arg(0);
+            let two = block.iconst(Type::Int32, 2);
+            let c = block.more([n, two]);
+            block.do_if(c).branch(&b2, &[n]).branch(&b3, &[two])
+        });
+        unit.with_block(b2, |mut block| {
+            let n = block.fetch_arg(0);
+            block.branch(&b3, &[n])
+        });
+        unit.with_block(b3, |mut block| {
+            let n = block.fetch_arg(0);
+            let one = block.iconst(Type::Int32, 1);
+            let r = block.add([n, one]);
+            block.ret(&[r])
+        });
+        unit.with_block(b4, |mut block| {
+            let z = block.iconst(Type::Int32, 0);
+            block.ret(&[z])
+        });
+        let unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        // every branch is decided, so what remains is merged into b0
+        assert_eq!(unit.blocks.len(), 1);
+        let last = *unit.blocks[Block(0)].insts.first().unwrap();
+        assert_eq!(unit.instructions[last].kind, InstKind::IConst(4));
+    }
+    #[test]
+    fn gvn() {
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O2;
+        let b0 = unit.new_block(&[Type::Int32]);
+        let b1 = unit.new_block(&[]);
+        let b2 = unit.new_block(&[]);
+        let mut n = Default::default();
+        unit.with_block(b0, |mut block| {
+            n = block.fetch_arg(0);
+            let two = block.iconst(Type::Int32, 2);
+            let c = block.less([n, two]);
+            block.do_if(c).branch(&b1, &[]).branch(&b2, &[])
+        });
+        unit.with_block(b1, |mut block| {
+            let two = block.iconst(Type::Int32, 2);
+            let a = block.sub([n, two]);
+            let b = block.sub([n, two]);
+            let fa = block.recurse(&[a]);
+            let fb = block.recurse(&[b]);
+            let x = block.add([fa, n]);
+            let y = block.add([n, fa]);
+            let z = block.add([fb, x]);
+            let o = block.add([z, y]);
+            block.ret(&[o])
+        });
+        unit.with_block(b2, |mut block| {
+            let two = block.iconst(Type::Int32, 2);
+            block.ret(&[two])
+        });
+        let unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        let count = |b: u32, f: fn(&InstKind) -> bool| {
+            unit.blocks[Block(b)]
+                .insts
+                .iter()
+                .filter(|i| f(&unit.instructions[**i].kind))
+                .count()
+        };
+        // both `sub n, 2` became `add n, -2` before being merged
+        assert_eq!(count(1, |k| matches!(k, InstKind::IConst(-2))), 1);
+        assert_eq!(count(2, |k| matches!(k, InstKind::IConst(_))), 0);
+        assert_eq!(count(1, |k| matches!(k, InstKind::Sub(_))), 0);
+        assert_eq!(count(1, |k| matches!(k, InstKind::Recur(_))), 2);
+        assert_eq!(count(1, |k| matches!(k, InstKind::Add(_))), 4);
+    }
+    #[test]
+    fn simplify_cfg() {
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O1;
+        let b0 = unit.new_block(&[Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32]);
+        let b2 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let b3 = unit.new_block(&[Type::Int32]);
+        let b4 = unit.new_block(&[]);
+        let b5 = unit.new_block(&[]);
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            block.do_if(n).branch(&b1, &[n]).branch(&b4, &[])
+        });
+        // forwards to b2, threaded away
+        unit.with_block(b1, |mut block| {
+            let n = block.fetch_arg(0);
+            block.branch(&b2, &[n, n])
+        });
+        unit.with_block(b2, |mut block| {
+            let a = block.fetch_arg(0);
+            let b = block.fetch_arg(1);
+            let s = block.add([a, b]);
+            block.branch(&b3, &[s])
+        });
+        // merged into b2
+        unit.with_block(b3, |mut block| {
+            let n = block.fetch_arg(0);
+            block.ret(&[n])
+        });
+        unit.with_block(b4, |mut block| {
+            let z = block.iconst(Type::Int32, 0);
+            block.ret(&[z])
+        });
+        // unreachable
+        unit.with_block(b5, |mut block| {
+            let z = block.iconst(Type::Int32, 1);
+            block.ret(&[z])
+        });
+        let unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        assert_eq!(unit.blocks.len(), 3);
+        let term = unit.terminator(Block(0)).to_vec();
+        let targets: Vec<_> = term
+            .iter()
+            .filter_map(|i| unit.instructions[*i].kind.get_block())
+            .collect();
+        assert_eq!(targets, vec![Block(1), Block(2)]);
+        let b1: Vec<_> = unit.blocks[Block(1)]
+            .insts
+            .iter()
+            .map(|i| unit.instructions[*i].kind)
+            .collect();
+        assert!(matches!(
+            b1[..],
+            [
+                InstKind::FetchArg(0),
+                InstKind::FetchArg(1),
+                InstKind::Add(_),
+                InstKind::Terminator(TermData::Branch(Block::MAX, _))
+            ]
+        ));
+
+        // b1 only fetches and branches, but b2 uses its parameter
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O1;
+        let b0 = unit.new_block(&[Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32]);
+        let b2 = unit.new_block(&[]);
+        let b3 = unit.new_block(&[]);
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            block.do_if(n).branch(&b1, &[n]).branch(&b3, &[])
+        });
+        let mut x = Default::default();
+        unit.with_block(b1, |mut block| {
+            x = block.fetch_arg(0);
+            block.branch(&b2, &[])
+        });
+        unit.with_block(b2, |mut block| {
+            let one = block.iconst(Type::Int32, 1);
+            let s = block.add([x, one]);
+            block.ret(&[s])
+        });
+        unit.with_block(b3, |mut block| {
+            let z = block.iconst(Type::Int32, 0);
+            block.ret(&[z])
+        });
+        let unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        let fetches = unit
+            .instructions
+            .iter()
+            .filter(|i| matches!(i.kind, InstKind::FetchArg(0)))
+            .count();
+        assert_eq!(fetches, 2);
+    }
+    #[test]
+    fn tail_recursion() {
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O1;
+        let b0 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let b2 = unit.new_block(&[Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            let acc = block.fetch_arg(1);
+            block.do_if(n).branch(&b1, &[n, acc]).branch(&b2, &[acc])
+        });
+        unit.with_block(b1, |mut block| {
+            let n = block.fetch_arg(0);
+            let acc = block.fetch_arg(1);
+            let one = block.iconst(Type::Int32, 1);
+            let m = block.sub([n, one]);
+            let sum = block.add([acc, n]);
+            let r = block.recurse(&[m, sum]);
+            block.ret(&[r])
+        });
+        unit.with_block(b2, |mut block| {
+            let acc = block.fetch_arg(0);
+            block.ret(&[acc])
+        });
+        let mut unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        assert!(unit
+            .instructions
+            .iter()
+            .all(|i| !matches!(i.kind, InstKind::Recur(_))));
+        let preds = unit.predecessors();
+        let doms = unit.dominators();
+        assert!(preds
+            .iter()
+            .any(|(b, ps)| ps.iter().any(|p| doms.dominates(*b, *p))));
+    }
+    #[test]
+    fn peephole() {
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32, Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let x = block.fetch_arg(0);
+            let y = block.fetch_arg(1);
+            let eight = block.iconst(Type::Int32, 8);
+            let m = block.mul([eight, x]);
+            let one = block.iconst(Type::Int32, 1);
+            let p = block.mul([m, one]);
+            let three = block.iconst(Type::Int32, 3);
+            let s = block.sub([p, three]);
+            let d = block.add([s, s]);
+            let c = block.more([d, y]);
+            block.ret(&[c])
+        });
+        let mut unit = unit.finalize(Type::Int32);
+        let swap = peephole::Rule {
+            name: "more-swap",
+            op: peephole::Op::More,
+            args: [peephole::Pat::Any(0), peephole::Pat::Any(1)],
+            into: peephole::Rewrite::Op(
+                peephole::Op::Less,
+                [peephole::Expr::Var(1), peephole::Expr::Var(0)],
+            ),
+        };
+        let mut rules = peephole::RULES.to_vec();
+        rules.push(swap);
+        let mut pm = PassManager::new();
+        pm.add(Peephole::new(rules)).add(RemoveUnused).add(Compact);
+        pm.run(&mut unit);
+        eprintln!("{}", unit.human_format());
+        let kinds: Vec<_> = unit.blocks[Block(0)]
+            .insts
+            .iter()
+            .map(|i| unit.instructions[*i].kind)
+            .collect();
+        let [x, y] = [0, 1].map(Instruction);
+        let [k3, shl, km3, add, k1, shl2] = [2, 3, 4, 5, 6, 7].map(Instruction);
+        assert_eq!(
+            kinds[2..9],
+            [
+                InstKind::IConst(3),
+                InstKind::Shl([x, k3]),
+                InstKind::IConst(-3),
+                InstKind::Add([shl, km3]),
+                InstKind::IConst(1),
+                InstKind::Shl([add, k1]),
+                InstKind::Less([y, shl2])
+            ]
+        );
+        // the comparison an if decides on ends up right in front of it
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let (mut c, mut s) = Default::default();
+        unit.with_block(b0, |mut block| {
+            let x = block.fetch_arg(0);
+            let y = block.fetch_arg(1);
+            c = block.less([x, y]);
+            s = block.add([x, y]);
+            block.do_if(c).ret(&[s]).ret(&[x])
+        });
+        let mut unit = unit.finalize(Type::Int32);
+        assert_eq!(Peephole::default().run(&mut unit), Changes::INSTS);
+        assert_eq!(unit.blocks[Block(0)].insts[2..4], [s, c]);
+        assert_eq!(Peephole::default().run(&mut unit), Changes::NONE);
+    }
+    #[test]
+    #[should_panic(expected = "Rule any-4 uses variable 4, but only 4 exist!")]
+    fn invalid_rule() {
+        Peephole::new(vec![peephole::Rule {
+            name: "any-4",
+            op: peephole::Op::Add,
+            args: [peephole::Pat::Any(4), peephole::Pat::Is(0)],
+            into: peephole::Rewrite::Var(4),
+        }]);
+    }
+    #[test]
+    fn dead_params() {
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let b2 = unit.new_block(&[Type::Int32, Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            let one = block.iconst(Type::Int32, 1);
+            block.branch(&b1, &[n, one])
+        });
+        // `b` only dies once `y` is gone
+        unit.with_block(b1, |mut block| {
+            let a = block.fetch_arg(0);
+            let b = block.fetch_arg(1);
+            block.branch(&b2, &[a, b])
+        });
+        unit.with_block(b2, |mut block| {
+            let x = block.fetch_arg(0);
+            block.ret(&[x])
+        });
+        let mut unit = unit.finalize(Type::Int32);
+        let mut pm = PassManager::new();
+        pm.add(DeadParams).add(RemoveUnused).add(Compact);
+        pm.run(&mut unit);
+        eprintln!("{}", unit.human_format());
+        for b in [1, 2] {
+            assert_eq!(
+                unit.signatures[unit.blocks[Block(b)].signature],
+                [Type::Int32]
+            );
+        }
+        let term = unit.terminator(Block(0))[0];
+        let InstKind::Terminator(TermData::Branch(_, d)) = unit.instructions[term].kind else {
+            unreachable!()
+        };
+        assert_eq!(unit.data[d], [Instruction(0)]);
+        assert_eq!(unit.blocks[Block(0)].insts.len(), 2);
+    }
+    #[test]
+    fn dot() {
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32]);
+        let mut n = Default::default();
+        unit.with_block(b0, |mut block| {
+            n = block.fetch_arg(0);
+            let one = block.iconst(Type::Int32, 1);
+            let c = block.less([n, one]);
+            block.do_if(c).ret(&[n]).branch(&b1, &[one])
+        });
+        unit.with_block(b1, |mut block| {
+            let x = block.fetch_arg(0);
+            let s = block.add([x, n]);
+            block.ret(&[s])
+        });
+        let unit = unit.finalize(Type::Int32);
+        let dot = unit.to_dot_with(DotOptions {
+            liveness: true,
+            dominators: true,
+        });
+        eprintln!("{dot}");
+        assert!(dot.starts_with("digraph unit {"));
+        assert!(dot.contains("b0 [label=\"{b0(p0: int32)|@0 = fetchArg p0\\l@1 = const 1\\l"));
+        assert!(dot.contains("ret [shape=oval, label=\"return(int32)\"];"));
+        assert!(dot.contains("@2 = less @0, @1\\l: if @2\\l|"));
+        assert!(dot.contains("b0 -> ret [label=\"true [@0]\"];"));
+        assert!(dot.contains("b0 -> b1 [label=\"false [@1]\"];"));
+        assert!(dot.contains("b1 -> ret [label=\"[@7]\"];"));
+        assert!(dot.contains("b0 -> b1 [style=dashed"));
+        assert!(dot.contains("|live out: @0\\l}"));
+    }
+    #[test]
+    fn licm() {
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O2;
+        let b0 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32, Type::Int32]);
+        let b2 = unit.new_block(&[]);
+        let b3 = unit.new_block(&[]);
+        let (mut k, mut i, mut acc) = Default::default();
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            k = block.fetch_arg(1);
+            let zero = block.iconst(Type::Int32, 0);
+            block.branch(&b1, &[n, zero])
+        });
+        let b1 = unit.with_block(b1, |mut block| {
+            i = block.fetch_arg(0);
+            acc = block.fetch_arg(1);
+            block.do_if(i).branch(&b2, &[]).branch(&b3, &[])
+        });
+        unit.with_block(b2, |mut block| {
+            let two = block.iconst(Type::Int32, 2);
+            let step = block.add([k, two]);
+            let sum = block.add([acc, step]);
+            let one = block.iconst(Type::Int32, 1);
+            let next = block.sub([i, one]);
+            block.branch(&b1, &[next, sum])
+        });
+        unit.with_block(b3, |block| block.ret(&[acc]));
+        let mut unit = unit.finalize(Type::Int32);
+        eprintln!("{}", unit.human_format());
+        let l = unit.loops().loops()[0].clone();
+        assert_eq!(unit.loops().loops().len(), 1);
+        assert_eq!(unit.loops().depth(l.header), 1);
+        // whatever is left in the loop depends on it
+        for b in l.body.iter() {
+            for &i in unit.blocks[*b].insts.iter() {
+                let ops = unit.instructions[i].kind.get_insts(&unit.data);
+                if matches!(
+                    unit.instructions[i].kind,
+                    InstKind::Add(_) | InstKind::Sub(_)
+                ) {
+                    assert!(ops
+                        .iter()
+                        .any(|o| l.body.contains(&unit.instructions[*o].block)));
+                }
+            }
+        }
+        let step = unit
+            .instructions
+            .iter()
+            .position(|i| matches!(i.kind, InstKind::Add(_)) && !l.body.contains(&i.block));
+        let step = Instruction(step.unwrap() as u32);
+        assert_eq!(unit.spill_weight(step), 10);
+    }
+    #[test]
+    fn inlining() {
+        let mut module = Module::new();
+        let mut helper = Unit::new();
+        let b0 = helper.new_block(&[Type::Int32]);
+        let b1 = helper.new_block(&[]);
+        let b2 = helper.new_block(&[]);
+        let mut n = Default::default();
+        helper.with_block(b0, |mut block| {
+            n = block.fetch_arg(0);
+            let zero = block.iconst(Type::Int32, 0);
+            let c = block.less([n, zero]);
+            block.do_if(c).branch(&b1, &[]).branch(&b2, &[])
+        });
+        helper.with_block(b1, |mut block| {
+            let zero = block.iconst(Type::Int32, 0);
+            let m = block.sub([zero, n]);
+            block.ret(&[m])
+        });
+        helper.with_block(b2, |block| block.ret(&[n]));
+        let abs = module.push(helper.finalize(Type::Int32));
+        let mut unit = Unit::new();
+        unit.settings.opt_level = OptLevel::O1;
+        let b0 = unit.new_block(&[Type::Int32, Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let x = block.fetch_arg(0);
+            let a = block.call(abs, Type::Int32, &[x]);
+            // fetched after a call, which the block is split at
+            let y = block.fetch_arg(1);
+            let b = block.call(abs, Type::Int32, &[y]);
+            let s = block.add([a, b]);
+            block.ret(&[s])
+        });
+        let main = module.push(unit.finalize(Type::Int32));
+        assert!(module.type_check().is_ok());
+        assert!(!module.inline(2));
+        assert!(module.inline(16));
+        let unit = module.get(main).unwrap();
         eprintln!("{}", unit.human_format());
+        assert!(unit
+            .instructions
+            .iter()
+            .all(|i| !matches!(i.kind, InstKind::Call(..))));
+        assert_eq!(unit.blocks.len(), 5);
+        assert_eq!(module.run(main, &[-3, 4]), Ok(Some(7)));
+        let mut bad = Unit::new();
+        let b0 = bad.new_block(&[]);
+        bad.with_block(b0, |mut block| {
+            let r = block.call(abs, Type::Int32, &[]);
+            block.ret(&[r])
+        });
+        module.push(bad.finalize(Type::Int32));
+        let errors = module.type_check().unwrap_err();
+        assert_eq!(
+            errors[0].1.kind,
+            TypeErrorKind::ArgCount {
+                expected: 1,
+                found: 0
+            }
+        );
+    }
+    #[test]
+    fn serialization() {
+        let mut helper = Unit::new();
+        let b0 = helper.new_block(&[Type::Int32]);
+        helper.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            let two = block.iconst(Type::Int32, -2);
+            let m = block.mul([n, two]);
+            block.ret(&[m])
+        });
+        let helper = helper.finalize(Type::Int32);
+        let bytes = helper.to_bytes();
+        let decoded = Unit::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.human_format(), helper.human_format());
+        assert_eq!(decoded.liveness, helper.liveness);
+        assert_eq!(decoded.to_bytes(), bytes);
+        // no prefix of a unit decodes, and none panics
+        for n in 0..bytes.len() {
+            assert!(Unit::from_bytes(&bytes[..n]).is_err());
+        }
+        let mut bad = bytes.clone();
+        bad[..4].copy_from_slice(b"RDXM");
+        assert_eq!(Unit::from_bytes(&bad).err(), Some(DecodeError::Magic));
+        bad = bytes.clone();
+        bad[4] = 9;
+        assert_eq!(Unit::from_bytes(&bad).err(), Some(DecodeError::Version(9)));
+        // the last word is the terminator listed in the last block
+        bad = bytes.clone();
+        let end = bad.len();
+        bad[end - 4..].copy_from_slice(&99u32.to_le_bytes());
+        assert!(matches!(
+            Unit::from_bytes(&bad),
+            Err(DecodeError::OutOfRange {
+                what: "instruction",
+                index: 99,
+                ..
+            })
+        ));
+        // listing @0 in its place leaves the terminator in no block
+        bad[end - 4..].copy_from_slice(&0u32.to_le_bytes());
+        let error = Unit::from_bytes(&bad).err().unwrap();
+        assert_eq!(error, DecodeError::Misplaced(Instruction(0)));
+        assert_eq!(error.to_string(), "@0 is not listed once in its block");
+        // units decoded without an error hold up to being printed
+        for n in 4..bytes.len() {
+            for flip in [0x01, 0x80] {
+                bad = bytes.clone();
+                bad[n] ^= flip;
+                if let Ok(unit) = Unit::from_bytes(&bad) {
+                    unit.canonical_format();
+                    unit.lifetime_table();
+                }
+            }
+        }
+        // finalized units are checked like `finalize` does
+        let mut open = Unit::new();
+        let b0 = open.new_block(&[]).block();
+        open.cursor(b0).iconst(Type::Int32, 1);
+        open.retsig = Some(Type::Void);
+        let error = Unit::from_bytes(&open.to_bytes()).err().unwrap();
+        assert_eq!(error, DecodeError::Unterminated(b0));
+        assert_eq!(error.to_string(), "b0 is not terminated");
+        // tombstones of removed blocks are kept until compaction
+        let mut dead = Unit::new();
+        let b0 = dead.new_block(&[]);
+        let b1 = dead.new_block(&[]);
+        dead.with_block(b0, |block| block.ret(&[]));
+        dead.with_block(b1, |block| block.ret(&[]));
+        dead.remove_blocks(&[Block(1)].into_iter().collect());
+        let decoded = Unit::from_bytes(&dead.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), dead.to_bytes());
+        let mut module = Module::new();
+        let abs = module.push(helper);
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let x = block.fetch_arg(0);
+            let a = block.call(abs, Type::Int32, &[x]);
+            block.ret(&[a])
+        });
+        module.push(unit.finalize(Type::Int32));
+        let decoded = Module::from_bytes(&module.to_bytes()).unwrap();
+        assert!(decoded.type_check().is_ok());
+        assert_eq!(decoded.to_bytes(), module.to_bytes());
+        // calls may not leave the module
+        let mut orphan = Unit::new();
+        let b0 = orphan.new_block(&[]);
+        orphan.with_block(b0, |mut block| {
+            let r = block.call(UnitId(7), Type::Int32, &[]);
+            block.ret(&[r])
+        });
+        let mut single = Module::new();
+        single.push(orphan.finalize(Type::Int32));
+        assert!(matches!(
+            Module::from_bytes(&single.to_bytes()),
+            Err(DecodeError::OutOfRange {
+                what: "unit",
+                index: 7,
+                len: 1
+            })
+        ));
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            let one = block.iconst(Type::Int32, 1);
+            let m = block.add([n, one]);
+            block.ret(&[m])
+        });
+        let unit = unit.finalize(Type::Int32);
+        let json = serde_json::to_string(&unit).unwrap();
+        eprintln!("{json}");
+        let decoded: Unit = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.human_format(), unit.human_format());
+        assert_eq!(decoded.liveness, unit.liveness);
+        // the add refers to an instruction that doesn't exist
+        let bad = json.replace("{\"Add\":[0,1]}", "{\"Add\":[0,42]}");
+        assert_ne!(bad, json);
+        assert!(serde_json::from_str::<Unit>(&bad).is_err());
+        // the add uses itself, so it is checked like `finalize` does
+        let bad = json.replace("{\"Add\":[0,1]}", "{\"Add\":[0,2]}");
+        let error = serde_json::from_str::<Unit>(&bad).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("@2 uses @2 which is not always defined"));
+    }
+    #[test]
+    fn parse_and_run() {
+        let text = "
+            // the sum of 1..=n, counting down
+            ---b0(p0: int32):
+            |   @n = fetchArg p0
+            |   : br b2 []
+            ---b1(p0: int32, p1: int32):
+            |   @i = fetchArg p0
+            |   @acc = fetchArg p1
+            |   @done = less @i, @one  // defined further down
+            |   : if @done
+            |   : ret [@acc]
+            |   : br b3 []
+            ---b2():
+            |   @one = const 1
+            |   @zero = const 0
+            |   : br b1 [@n, @zero]
+            ---b3():
+            |   @next = sub @i, @one
+            |   @sum = add @acc, @i
+            |   : br b1 [@next, @sum]
+            ---return(int32)
+        ";
+        let (mut unit, ret) = Unit::parse(text).unwrap();
+        unit.settings.volatile = false;
+        let unit = unit.finalize(ret);
+        assert_eq!(unit.run(&[10]), Ok(Some(55)));
+        assert_eq!(
+            unit.run(&[]),
+            Err(RunError::ArgCount {
+                expected: 1,
+                found: 0
+            })
+        );
+        // printing and parsing again gives the same unit
+        let (again, ret) = Unit::parse(&unit.canonical_format()).unwrap();
+        let again = again.finalize(ret);
+        assert_eq!(again.canonical_format(), unit.canonical_format());
+        let error = Unit::parse("---b0():\n|\t@x = add @x, @y\n|\t: ret []\n---return()")
+            .err()
+            .unwrap();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "@y is not defined");
+        let error = Unit::parse("---b0():\n|\t@x = const 1\n---return()")
+            .err()
+            .unwrap();
+        assert_eq!(error.message, "block is not terminated");
+        // calls name their type, as the callee is not known
+        let (unit, _) =
+            Unit::parse("---b0():\n|\t@x = call void u1 []\n|\t: ret []\n---return()").unwrap();
+        assert_eq!(unit.instructions[Instruction(0)].typing, Type::Void);
+        assert!(unit.human_format().contains("@0 = call void u1 []"));
+        // units that were not finalized fail to run instead of panicking
+        let mut open = Unit::new();
+        let b0 = open.new_block(&[]).block();
+        open.cursor(b0).iconst(Type::Int32, 1);
+        assert_eq!(open.run(&[]), Err(RunError::Unterminated(b0)));
+        open.cursor(b0).fetch_arg(0);
+        assert_eq!(
+            open.run(&[]),
+            Err(RunError::ArgIndex {
+                block: b0,
+                index: 0
+            })
+        );
+    }
+    #[test]
+    fn repl() {
+        let mut repl = Repl::new();
+        let mut input = |line: &str| repl.input(line);
+        assert!(input("@x = const 1").is_err());
+        for line in [
+            "b0(p0: int32):",
+            "return(int32)",
+            "@n = fetchArg p0",
+            "@one = const 1",
+        ] {
+            input(line).unwrap();
+        }
+        assert_eq!(
+            input("@m = add @n, @two"),
+            Err("@two is not defined".into())
+        );
+        assert_eq!(input("@m = add @n, @one"), Ok("@m = @2".into()));
+        assert_eq!(input(".run 4"), Err("b0 is not terminated".into()));
+        input(": ret [@n]").unwrap();
+        assert_eq!(input(".undo"), Ok(String::new()));
+        // rejected instructions are not added to the block
+        assert!(input(": br b0 []")
+            .unwrap_err()
+            .contains("expected 1 arguments"));
+        assert_eq!(input(": ret [@m]"), Ok(String::new()));
+        assert_eq!(
+            input("@y = const 1"),
+            Err("b0 is already terminated".into())
+        );
+        assert_eq!(input(".run 4"), Ok("5".into()));
+        assert!(input(".liveness").unwrap().contains("| ___ | once @1"));
+        assert_eq!(input("// just a comment"), Ok(String::new()));
+    }
+    #[test]
+    fn source_locations() {
+        let text = "
+            ---b0(p0: int32):
+            |   @n = fetchArg p0  // f0:1:8
+            |   @one = const 1    // f0:2:9
+            |   @two = add @one, @one  // f0:2:13
+            |   @m = mul @n, @two // f0:3:5
+            |   : ret [@m]        // f0:4:1
+            ---return(int32)
+        ";
+        let (mut unit, ret) = Unit::parse(text).unwrap();
+        unit.settings.volatile = false;
+        unit.settings.opt_level = OptLevel::O2;
+        let unit = unit.finalize(ret);
+        // folded and rewritten instructions keep their location
+        let lines: Vec<u32> = unit.locations().map(|(_, loc)| loc.line).collect();
+        assert_eq!(lines.first(), Some(&1));
+        assert_eq!(lines.last(), Some(&4));
+        assert!(lines.contains(&3));
+        assert!(unit.human_format().contains("// f0:4:1"));
+        let (again, ret) = Unit::parse(&unit.canonical_format()).unwrap();
+        let again = again.finalize(ret);
+        assert!(again.locations().eq(unit.locations()));
+        let copy = Unit::from_bytes(&unit.to_bytes()).unwrap();
+        assert!(copy.locations().eq(unit.locations()));
+
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[]);
+        unit.with_block(b0, |mut block| {
+            block.set_loc(Some(SourceLoc {
+                file: 2,
+                line: 7,
+                column: 3,
+            }));
+            block.fetch_arg(0);
+            block.ret(&[])
+        });
+        let errors = unit.type_check().unwrap_err();
+        assert!(errors[0].to_string().starts_with("@0 in b0 at f2:7:3: "));
+    }
+    #[test]
+    fn type_errors() {
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32]);
+        let b1 = unit.new_block(&[Type::Int32]);
+        let (mut bad_arg, mut bad_const, mut bad_add) = Default::default();
+        unit.with_block(b0, |mut block| {
+            bad_arg = block.fetch_arg(3);
+            bad_const = block.iconst(Type::Int32, 1 << 40);
+            let v = block.iconst(Type::Void, 0);
+            let n = block.fetch_arg(0);
+            bad_add = block.add([n, v]);
+            block.branch(&b1, &[])
+        });
+        unit.with_block(b1, |block| block.ret(&[]));
+        let errors = unit.type_check().unwrap_err();
+        let kinds: Vec<_> = errors.iter().map(|e| (e.inst, e.kind.clone())).collect();
+        assert!(kinds.contains(&(bad_arg, TypeErrorKind::ArgIndex { index: 3, len: 1 })));
+        assert!(kinds.contains(&(bad_const, TypeErrorKind::ConstRange(1 << 40, Type::Int32))));
+        assert!(kinds.iter().any(|(i, _)| *i == bad_add));
+        assert!(errors.iter().any(|e| e.kind
+            == TypeErrorKind::ArgCount {
+                expected: 1,
+                found: 0
+            }));
+        // the result of recursion is not void before finalizing
+        let mut unit = Unit::new();
+        let b0 = unit.new_block(&[Type::Int32]);
+        unit.with_block(b0, |mut block| {
+            let n = block.fetch_arg(0);
+            let r = block.recurse(&[n]);
+            let s = block.add([r, n]);
+            let c = block.less([s, n]);
+            block.ret(&[c])
+        });
+        assert_eq!(unit.type_check(), Ok(()));
+        let unit = unit.finalize(Type::Int32);
+        assert_eq!(unit.instructions[Instruction(1)].typing, Type::Int32);
     }
     #[test]
     #[should_panic]
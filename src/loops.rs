@@ -0,0 +1,124 @@
+use crate::data::{Block, Instruction, Map, Set, Unit};
+use crate::dominators::Dominators;
+
+// Loop forest
+//   Natural loops are found from back edges, aka branches
+//   to a block dominating the branching one. Loops sharing
+//   a header are merged into one
+
+/// A natural loop.
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: Block,
+    /// Every block of the loop, including the header
+    /// and the blocks of nested loops.
+    pub body: Set<Block>,
+    /// Blocks outside the loop branched to from inside.
+    pub exits: Set<Block>,
+    /// Index of the innermost loop containing this one.
+    pub parent: Option<usize>,
+    /// 1 for outermost loops.
+    pub depth: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Loops {
+    // outer loops come before the loops they contain
+    loops: Vec<Loop>,
+    innermost: Map<Block, usize>,
+}
+
+// how much more a use counts for each loop it is nested in
+const LOOP_WEIGHT: usize = 10;
+
+impl Loops {
+    pub fn compute(unit: &Unit, doms: &Dominators) -> Self {
+        let preds = unit.predecessors();
+        let mut bodies = Map::<Block, Set<Block>>::new();
+        for &header in doms.reverse_postorder() {
+            for &p in preds.get(&header).into_iter().flatten() {
+                if !doms.dominates(header, p) {
+                    continue;
+                }
+                // everything reaching the back edge without
+                // passing the header belongs to the loop
+                let body = bodies.entry(header).or_insert_with(|| Set::from([header]));
+                let mut work = vec![p];
+                while let Some(b) = work.pop() {
+                    if doms.is_reachable(b) && body.insert(b) {
+                        work.extend(preds.get(&b).into_iter().flatten());
+                    }
+                }
+            }
+        }
+        let mut loops: Vec<Loop> = bodies
+            .into_iter()
+            .map(|(header, body)| {
+                let exits = body
+                    .iter()
+                    .flat_map(|b| unit.successors(*b))
+                    .filter(|s| !body.contains(s))
+                    .collect();
+                Loop {
+                    header,
+                    body,
+                    exits,
+                    parent: None,
+                    depth: 1,
+                }
+            })
+            .collect();
+        // nested loops are strictly smaller, so the parent of a loop
+        // is the last loop before it containing its header
+        loops.sort_by_key(|l| std::cmp::Reverse(l.body.len()));
+        let mut innermost = Map::new();
+        for i in 0..loops.len() {
+            let header = loops[i].header;
+            if let Some(p) = (0..i).rev().find(|p| loops[*p].body.contains(&header)) {
+                loops[i].parent = Some(p);
+                loops[i].depth = loops[p].depth + 1;
+            }
+            for b in loops[i].body.iter() {
+                innermost.insert(*b, i);
+            }
+        }
+        Self { loops, innermost }
+    }
+    /// Every loop, outer loops before the ones nested in them.
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+    /// The innermost loop containing a block.
+    pub fn innermost(&self, block: Block) -> Option<&Loop> {
+        self.innermost.get(&block).map(|i| &self.loops[*i])
+    }
+    /// The amount of loops a block is nested in.
+    pub fn depth(&self, block: Block) -> usize {
+        self.innermost(block).map_or(0, |l| l.depth)
+    }
+}
+
+impl Unit {
+    /// The loop forest, computed if not cached.
+    pub fn loops(&mut self) -> &Loops {
+        if self.analyses.loops.is_none() {
+            self.dominators();
+            let loops = Loops::compute(self, self.analyses.dominators.as_ref().unwrap());
+            self.analyses.loops = Some(loops);
+        }
+        self.analyses.loops.as_ref().unwrap()
+    }
+    /// The cost of spilling a value for register allocation,
+    /// every use counts ten times as much per enclosing loop.
+    pub fn spill_weight(&mut self, inst: Instruction) -> usize {
+        let blocks: Vec<Block> = self
+            .users(inst)
+            .map(|u| self.instructions[u].block)
+            .collect();
+        let loops = self.loops();
+        blocks
+            .into_iter()
+            .map(|b| LOOP_WEIGHT.pow(loops.depth(b) as u32))
+            .sum()
+    }
+}
@@ -0,0 +1,188 @@
+use radix::{DotOptions, OptLevel, PassManager, Repl, Unit};
+use std::io::{BufRead, Write};
+use std::process::ExitCode;
+
+// Command line driver
+//   Reads a unit in the text format, verifies it, runs the
+//   chosen passes and emits the result in the chosen way
+
+const USAGE: &str = "\
+usage: radix <command> <file.radix> [options]
+       radix repl
+
+commands:
+    check           verify the unit and report errors
+    fmt             print the unit after running the passes
+    dot             print the control flow graph for Graphviz
+    run             run the unit with the interpreter
+    asm, obj        emit assembly or an object file
+    repl            build a unit interactively
+
+options:
+    -O0, -O1, -O2   select the optimization preset (default -O0)
+    --passes a,b    run the named passes instead of the preset
+    --canonical     number values in order when printing
+    --liveness      show liveness in the graph
+    --dominators    show dominators in the graph
+    --args 1 2 ..   arguments to run the unit with
+    -o <file>       write the output to a file instead of stdout";
+
+struct Options {
+    command: String,
+    file: String,
+    opt_level: OptLevel,
+    passes: Option<String>,
+    canonical: bool,
+    dot: DotOptions,
+    args: Vec<isize>,
+    output: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let command = args.next().ok_or("missing command")?;
+    let file = args.next().ok_or("missing input file")?;
+    let mut options = Options {
+        command,
+        file,
+        opt_level: OptLevel::O0,
+        passes: None,
+        canonical: false,
+        dot: DotOptions::default(),
+        args: Vec::new(),
+        output: None,
+    };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-O0" => options.opt_level = OptLevel::O0,
+            "-O1" => options.opt_level = OptLevel::O1,
+            "-O2" => options.opt_level = OptLevel::O2,
+            "--passes" => options.passes = Some(args.next().ok_or("--passes needs a list")?),
+            "--canonical" => options.canonical = true,
+            "--liveness" => options.dot.liveness = true,
+            "--dominators" => options.dot.dominators = true,
+            "-o" => options.output = Some(args.next().ok_or("-o needs a file")?),
+            "--args" => {
+                // everything up to the next option, numbers may be negative
+                while let Some(n) =
+                    args.next_if(|a| !a.starts_with('-') || a.parse::<isize>().is_ok())
+                {
+                    let n = n.parse().map_err(|_| format!("`{n}` is not a number"))?;
+                    options.args.push(n);
+                }
+            }
+            arg => return Err(format!("unknown option `{arg}`")),
+        }
+    }
+    Ok(options)
+}
+
+fn compile(options: &Options) -> Result<Unit, String> {
+    let text =
+        std::fs::read_to_string(&options.file).map_err(|e| format!("{}: {e}", options.file))?;
+    let (mut unit, ret) = Unit::parse(&text).map_err(|e| format!("{}:{e}", options.file))?;
+    unit.settings.volatile = false;
+    unit.settings.opt_level = match options.passes {
+        Some(_) => OptLevel::O0,
+        None => options.opt_level,
+    };
+    // report type errors before `finalize` would panic on them
+    if let Err(errors) = unit.type_check() {
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|e| format!("{}: {e}", options.file))
+            .collect();
+        return Err(errors.join("\n"));
+    }
+    if let Some((i, u)) = unit.undefined_use() {
+        return Err(format!(
+            "{}: {i} uses {u} which is not always defined",
+            options.file
+        ));
+    }
+    let mut unit = unit.finalize(ret);
+    if let Some(passes) = &options.passes {
+        let mut pm = PassManager::new();
+        for name in passes.split(',').filter(|n| !n.is_empty()) {
+            if !pm.add_named(name) {
+                return Err(format!("unknown pass `{name}`"));
+            }
+        }
+        pm.add_named("remove-unused");
+        pm.add_named("compact");
+        pm.add_named("liveness");
+        pm.run(&mut unit);
+    }
+    Ok(unit)
+}
+
+fn run(options: &Options) -> Result<String, String> {
+    let unit = compile(options)?;
+    Ok(match options.command.as_str() {
+        "check" => String::new(),
+        "fmt" if options.canonical => unit.canonical_format(),
+        "fmt" => unit.human_format(),
+        "dot" => unit.to_dot_with(options.dot),
+        "run" => match unit.run(&options.args).map_err(|e| e.to_string())? {
+            Some(n) => format!("{n}\n"),
+            None => String::new(),
+        },
+        "asm" | "obj" => return Err("radix has no code generator yet".into()),
+        command => return Err(format!("unknown command `{command}`")),
+    })
+}
+
+fn repl() -> ExitCode {
+    let mut repl = Repl::new();
+    println!("radix repl, enter `.help` for help");
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush().unwrap();
+        let Some(Ok(line)) = lines.next() else {
+            return ExitCode::SUCCESS;
+        };
+        if matches!(line.trim(), ".quit" | ".exit") {
+            return ExitCode::SUCCESS;
+        }
+        match repl.input(&line) {
+            Ok(out) if out.is_empty() => {}
+            Ok(out) => println!("{}", out.trim_end()),
+            Err(e) => println!("error: {e}"),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    if matches!(
+        std::env::args().nth(1).as_deref(),
+        None | Some("help" | "-h" | "--help")
+    ) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    if std::env::args().nth(1).as_deref() == Some("repl") {
+        return repl();
+    }
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let written = run(&options).and_then(|out| match &options.output {
+        Some(path) => std::fs::write(path, out).map_err(|e| format!("{path}: {e}")),
+        None => {
+            print!("{out}");
+            Ok(())
+        }
+    });
+    match written {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
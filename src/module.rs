@@ -0,0 +1,208 @@
+use crate::data::{Block, InstKind, Instruction, Map, TermData, Type, Unit, UnitId};
+use crate::passes::{Changes, PassManager};
+use crate::typecheck::{TypeError, TypeErrorKind};
+use crate::util::KeyVec;
+
+// Modules
+//   A module is a collection of units that may call
+//   each other, which is what inlining works across
+
+#[derive(Default)]
+pub struct Module {
+    pub(crate) units: KeyVec<UnitId, Unit>,
+}
+
+impl Module {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds a finalized unit to the module.
+    pub fn push(&mut self, unit: Unit) -> UnitId {
+        self.units.push(unit)
+    }
+    /// The id the next pushed unit will get,
+    /// for calling units that aren't built yet.
+    pub fn next_id(&self) -> UnitId {
+        self.units.next_idx()
+    }
+    pub fn get(&self, id: UnitId) -> Option<&Unit> {
+        self.units.get(id)
+    }
+    pub fn get_mut(&mut self, id: UnitId) -> Option<&mut Unit> {
+        self.units.get_mut(id)
+    }
+    /// Checks every call against the signature of its callee.
+    pub fn type_check(&self) -> Result<(), Vec<(UnitId, TypeError)>> {
+        let mut errors = Vec::new();
+        for (u, unit) in self.units.iter().enumerate() {
+            let u = UnitId(u as u32);
+            for (i, inst) in unit.instructions.iter().enumerate() {
+                let InstKind::Call(callee, d) = inst.kind else {
+                    continue;
+                };
+                let (block, inst_id, loc) = (inst.block, Instruction(i as u32), inst.loc);
+                let mut error = |kind| {
+                    errors.push((
+                        u,
+                        TypeError {
+                            block,
+                            inst: inst_id,
+                            loc,
+                            kind,
+                        },
+                    ))
+                };
+                let Some(callee) = self.units.get(callee) else {
+                    error(TypeErrorKind::UnknownUnit(callee));
+                    continue;
+                };
+                let sig = callee
+                    .blocks
+                    .get(Block(0))
+                    .map(|b| &callee.signatures[b.signature]);
+                unit.check_args(&unit.data[d], sig.unwrap_or(&[]), &mut error);
+                let expected = callee.retsig.unwrap_or(Type::Void);
+                if inst.typing != expected {
+                    error(TypeErrorKind::CallResult {
+                        expected,
+                        found: inst.typing,
+                    });
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+    /// Inlines every call to another unit of at most `threshold`
+    /// in size, then reruns the pipeline of the changed units.
+    /// Calls brought in by inlining are left for the next run,
+    /// so mutually recursive units are never expanded endlessly.
+    pub fn inline(&mut self, threshold: usize) -> bool {
+        let mut changed = false;
+        for u in (0..self.units.len()).map(|u| UnitId(u as u32)) {
+            let calls: Vec<(Instruction, UnitId)> = self.units[u]
+                .instructions
+                .iter()
+                .enumerate()
+                .filter_map(|(i, inst)| match inst.kind {
+                    InstKind::Call(callee, _) if callee != u => {
+                        Some((Instruction(i as u32), callee))
+                    }
+                    _ => None,
+                })
+                .filter(|(_, callee)| {
+                    self.units
+                        .get(*callee)
+                        .is_some_and(|c| c.size() <= threshold)
+                })
+                .collect();
+            if calls.is_empty() {
+                continue;
+            }
+            // the caller is taken out so the callees can be read
+            let mut unit = std::mem::take(&mut self.units[u]);
+            for (call, callee) in calls {
+                unit.inline_call(call, &self.units[callee]);
+            }
+            PassManager::preset(unit.settings.opt_level).run(&mut unit);
+            self.units[u] = unit;
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl Unit {
+    /// The cost of inlining this unit, the amount of
+    /// instructions not counting block parameters.
+    pub fn size(&self) -> usize {
+        self.blocks
+            .iter()
+            .flat_map(|b| b.insts.iter())
+            .filter(|i| !matches!(self.instructions[**i].kind, InstKind::FetchArg(_)))
+            .count()
+    }
+    // replaces a call by a copy of the callee's blocks,
+    // the block containing the call is split in two and
+    // every return of the callee branches to the second half
+    pub(crate) fn inline_call(&mut self, call: Instruction, callee: &Unit) {
+        let InstKind::Call(id, d) = self.instructions[call].kind else {
+            panic!("Instruction {} is not a call!", call);
+        };
+        let args = self.data[d].to_vec();
+        let block = self.instructions[call].block;
+        let ret = self.instructions[call].typing;
+        let loc = self.instructions[call].loc;
+        let sig: &[Type] = match ret {
+            Type::Void => &[],
+            _ => std::slice::from_ref(&ret),
+        };
+        let cont = self.new_block(sig).block();
+        let pos = self.blocks[block]
+            .insts
+            .iter()
+            .position(|i| *i == call)
+            .unwrap();
+        let rest = self.blocks[block].insts.split_off(pos + 1);
+        // parameters stay with the signature they are fetched from
+        let (params, rest): (Vec<_>, Vec<_>) = rest
+            .into_iter()
+            .partition(|i| matches!(self.instructions[*i].kind, InstKind::FetchArg(_)));
+        self.blocks[block].insts.extend(params);
+        if ret != Type::Void {
+            let result = self.cursor(cont).fetch_arg(0);
+            self.replace_all_uses(call, result);
+        }
+        for &i in rest.iter() {
+            self.instructions[i].block = cont;
+        }
+        self.blocks[cont].insts.extend(rest);
+        self.remove(call);
+        // unreachable blocks of the callee are not copied
+        let order = callee.reverse_postorder();
+        let blocks: Map<Block, Block> = order
+            .iter()
+            .map(|b| {
+                let sig = &callee.signatures[callee.blocks[*b].signature];
+                (*b, self.new_block(sig).block())
+            })
+            .collect();
+        // definitions dominate their uses, so in reverse
+        // postorder operands are always copied first
+        let mut insts = Map::new();
+        for b in order {
+            for &i in callee.blocks[b].insts.iter() {
+                let inst = &callee.instructions[i];
+                let mut kind = match inst.kind {
+                    // recursion in the callee calls the callee
+                    InstKind::Recur(d) => InstKind::Call(id, d),
+                    kind => kind,
+                };
+                if let InstKind::Call(_, d) | InstKind::Terminator(TermData::Branch(_, d)) =
+                    &mut kind
+                {
+                    *d = self.data.push_slice(&callee.data[*d]);
+                }
+                // returns continue after the call
+                if let InstKind::Terminator(TermData::Branch(t, _)) = &mut kind {
+                    *t = blocks.get(t).copied().unwrap_or(cont);
+                }
+                for op in kind.get_insts_mut(&mut self.data) {
+                    *op = insts[op];
+                }
+                let mut cursor = self.cursor(blocks[&b]);
+                cursor.set_loc(inst.loc);
+                let copy = cursor.insert(kind, inst.typing);
+                insts.insert(i, copy);
+            }
+        }
+        let mut cursor = self.cursor(block);
+        cursor.set_loc(loc);
+        cursor.branch(blocks[&Block(0)], &args);
+        self.liveness.clear();
+        self.invalidate(Changes::ALL);
+    }
+}
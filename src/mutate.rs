@@ -0,0 +1,208 @@
+use crate::data::{
+    Block, DataPart, InstData, InstKind, Instruction, Map, Set, SourceLoc, TermData, Type, Unit,
+};
+use crate::passes::Changes;
+
+// In-place mutation of a unit
+//   These keep the block lists and the user index
+//   consistent, so passes don't have to rebuild
+//   instructions by hand
+
+/// A branch target along with the arguments passed to it.
+/// `Block::MAX` stands for returning from the unit.
+pub struct Target<'a>(pub Block, pub &'a [Instruction]);
+
+/// The terminator of a block, as given to `Unit::set_terminator`.
+pub enum Terminator<'a> {
+    Branch(Target<'a>),
+    If(Instruction, Target<'a>, Target<'a>),
+}
+
+impl Unit {
+    /// Stores a list of arguments, as used by `Recur`, `Call` and `Branch`.
+    pub fn push_args(&mut self, args: &[Instruction]) -> [DataPart; 2] {
+        self.data.push_slice(args)
+    }
+    /// The instructions using the given instruction.
+    pub fn users(&self, inst: Instruction) -> impl Iterator<Item = Instruction> + '_ {
+        let mut last = None;
+        // uses are ordered by user, so dropping repeats is enough
+        self.uses
+            .of(inst)
+            .map(|u| u.user)
+            .filter(move |u| last.replace(*u) != Some(*u))
+    }
+    /// Rewrites every operand referring to `old` to refer to `new`.
+    pub fn replace_all_uses(&mut self, old: Instruction, new: Instruction) {
+        if old == new {
+            return;
+        }
+        // a value taking the place of another one stands
+        // for the same source, if it had none of its own
+        if self.instructions[new].loc.is_none() {
+            self.instructions[new].loc = self.instructions[old].loc;
+        }
+        let uses = self.uses.take(old);
+        for u in uses.iter() {
+            let kind = &mut self.instructions[u.user].kind;
+            kind.get_insts_mut(&mut self.data)[u.operand] = new;
+        }
+        self.uses.extend(new, uses);
+        self.invalidate(Changes::INSTS);
+    }
+    /// Inserts an instruction of the given type in front of
+    /// another one, taking over its source location.
+    pub fn insert_before(
+        &mut self,
+        inst: Instruction,
+        kind: InstKind,
+        typing: Type,
+    ) -> Instruction {
+        let block = self.instructions[inst].block;
+        let loc = self.instructions[inst].loc;
+        let mut cursor = self.cursor(block);
+        cursor.set_loc(loc);
+        cursor.goto_before(inst);
+        cursor.insert(kind, typing)
+    }
+    /// Replaces what an instruction does, keeping its index
+    /// and with it all of its uses.
+    pub fn rewrite(&mut self, inst: Instruction, kind: InstKind) {
+        let block = self.instructions[inst].block;
+        let old = std::mem::replace(&mut self.instructions[inst].kind, kind);
+        self.uses.remove(inst, &old, &self.data);
+        if let Some(t) = self.infer_type(&kind, block) {
+            self.instructions[inst].typing = t;
+        }
+        self.add_uses(inst);
+    }
+    /// Removes an unused instruction from its block.
+    /// Its index stays valid, but refers to a tombstone.
+    pub fn remove(&mut self, inst: Instruction) {
+        if self.users(inst).next().is_some() {
+            panic!("Instruction {} is removed but still used!", inst);
+        }
+        let block = self.instructions[inst].block;
+        self.blocks[block].insts.retain(|i| *i != inst);
+        self.tombstone(inst);
+    }
+    /// The source location of an instruction, if it has one.
+    pub fn loc(&self, inst: Instruction) -> Option<SourceLoc> {
+        self.instructions.get(inst)?.loc
+    }
+    pub fn set_loc(&mut self, inst: Instruction, loc: Option<SourceLoc>) {
+        self.instructions[inst].loc = loc;
+    }
+    /// The instructions carrying a source location in block
+    /// order, as the line table of debug info lists them.
+    pub fn locations(&self) -> impl Iterator<Item = (Instruction, SourceLoc)> + '_ {
+        self.blocks
+            .iter()
+            .flat_map(|b| b.insts.iter())
+            .filter_map(|i| Some((*i, self.instructions[*i].loc?)))
+    }
+    /// Replaces the terminator of a block, or terminates it.
+    pub fn set_terminator(&mut self, block: Block, term: Terminator) {
+        let start = self.blocks[block].term_start(&self.instructions);
+        let old = self.blocks[block].insts.split_off(start);
+        let loc = old.first().and_then(|i| self.instructions[*i].loc);
+        self.invalidate(Changes::ALL);
+        for i in old {
+            self.tombstone(i);
+        }
+        let mut cursor = self.cursor(block);
+        cursor.set_loc(loc);
+        match term {
+            Terminator::Branch(Target(b, args)) => {
+                cursor.branch(b, args);
+            }
+            Terminator::If(c, Target(t, targs), Target(f, fargs)) => {
+                cursor.do_if(c);
+                cursor.branch(t, targs);
+                cursor.branch(f, fargs);
+            }
+        }
+    }
+    /// Deletes blocks along with their instructions, renumbering
+    /// the remaining ones. Branches to deleted blocks must be
+    /// removed beforehand, the entry block cannot be deleted.
+    pub fn remove_blocks(&mut self, dead: &Set<Block>) {
+        assert!(
+            !dead.contains(&Block(0)),
+            "The entry block cannot be removed!"
+        );
+        for b in dead.iter() {
+            for i in std::mem::take(&mut self.blocks[*b].insts) {
+                self.tombstone(i);
+            }
+        }
+        let mut map = Map::new();
+        let blocks = std::mem::take(&mut self.blocks);
+        for (b, blockdata) in blocks.into_iter().enumerate() {
+            let b = Block(b as u32);
+            if !dead.contains(&b) {
+                map.insert(b, self.blocks.push(blockdata));
+            }
+        }
+        for inst in self.instructions.iter_mut() {
+            // tombstones of deleted blocks are left dangling
+            inst.block = map.get(&inst.block).copied().unwrap_or(Block::MAX);
+            if let InstKind::Terminator(TermData::Branch(b, _)) = &mut inst.kind {
+                if *b != Block::MAX {
+                    *b = map[b];
+                }
+            }
+        }
+        self.liveness.clear();
+        self.invalidate(Changes::ALL);
+    }
+    // turns an instruction into a tombstone and drops
+    // its uses, leaving it in whatever block it was in
+    pub(crate) fn tombstone(&mut self, inst: Instruction) {
+        let block = self.instructions[inst].block;
+        let old = std::mem::replace(
+            &mut self.instructions[inst],
+            InstData {
+                block,
+                kind: InstKind::Tombstone,
+                typing: Type::Void,
+                loc: None,
+            },
+        );
+        self.uses.remove(inst, &old.kind, &self.data);
+        self.invalidate(old.kind.changes());
+    }
+    pub(crate) fn add_uses(&mut self, inst: Instruction) {
+        self.uses
+            .add(inst, &self.instructions[inst].kind, &self.data);
+        self.invalidate(self.instructions[inst].kind.changes());
+    }
+}
+
+impl InstKind {
+    // terminators make up the control flow graph
+    fn changes(&self) -> Changes {
+        match self {
+            Self::Terminator(_) => Changes::ALL,
+            _ => Changes::INSTS,
+        }
+    }
+    pub(crate) fn get_insts_mut<'a>(
+        &'a mut self,
+        data: &'a mut crate::util::KeyVec<DataPart, Instruction>,
+    ) -> &'a mut [Instruction] {
+        match self {
+            Self::Add(a)
+            | Self::Sub(a)
+            | Self::Mul(a)
+            | Self::Shl(a)
+            | Self::Less(a)
+            | Self::More(a) => a,
+            Self::Recur(a) | Self::Call(_, a) | Self::Terminator(TermData::Branch(_, a)) => {
+                &mut data[*a]
+            }
+            Self::Terminator(TermData::DoIf(i)) => std::slice::from_mut(i),
+            _ => &mut [],
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::builder::Cursor;
+use crate::data::{Block, Instruction, Map, SourceLoc, Type, Unit, UnitId};
+
+// Text parser
+//   Reads back what `human_format` and `canonical_format` print,
+//   the leading `---` and `|` are optional and `//` starts a
+//   comment. Values may be named by anything following an `@`
+//   and be used before the line defining them. A comment
+//   like `// f0:12:5` gives the source location of a line.
+//
+//   ---b0(p0: int32):
+//   |   @n = fetchArg p0
+//   |   @one = const 1
+//   |   @m = add @n, @one
+//   |   : ret [@m]
+//   ---return(int32)
+
+/// Where and why the text of a unit could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+impl std::error::Error for ParseError {}
+
+// an instruction as written, operands still by name
+pub(crate) enum Op<'a> {
+    FetchArg(usize),
+    IConst(isize),
+    Binary(&'a str, [&'a str; 2]),
+    Recur(Vec<&'a str>),
+    Call(UnitId, Type, Vec<&'a str>),
+    If(&'a str),
+    Branch(Block, Vec<&'a str>),
+}
+
+struct Line<'a> {
+    line: usize,
+    name: Option<&'a str>,
+    op: Op<'a>,
+    loc: Option<SourceLoc>,
+}
+
+struct BlockText<'a> {
+    line: usize,
+    sig: Vec<Type>,
+    lines: Vec<Line<'a>>,
+}
+
+impl Unit {
+    /// Parses a unit from its text format. The unit is not
+    /// finalized yet, so its settings may still be changed
+    /// before calling `Unit::finalize` with the return type.
+    pub fn parse(text: &str) -> Result<(Unit, Type), ParseError> {
+        let mut blocks: Vec<BlockText> = Vec::new();
+        let mut ret = None;
+        for (n, line) in text.lines().enumerate() {
+            let n = n + 1;
+            let error = |message: String| ParseError { line: n, message };
+            let (line, comment) = line.split_once("//").unwrap_or((line, ""));
+            let line = line.trim();
+            let line = line.trim_start_matches('-').trim_start_matches('|').trim();
+            if line.is_empty() {
+                continue;
+            }
+            if ret.is_some() {
+                return Err(error("nothing may follow the return type".into()));
+            }
+            if let Some(rest) = line.strip_prefix("return(") {
+                let t = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| error("expected `)`".into()))?;
+                ret = Some(match t.trim() {
+                    "" => Type::Void,
+                    t => parse_type(t).map_err(error)?,
+                });
+            } else if line.starts_with('b') {
+                let (b, sig) = parse_header(line).map_err(error)?;
+                if b != Block(blocks.len() as u32) {
+                    let expected = Block(blocks.len() as u32);
+                    return Err(error(format!("expected block {expected}, found {b}")));
+                }
+                blocks.push(BlockText {
+                    line: n,
+                    sig,
+                    lines: Vec::new(),
+                });
+            } else {
+                let Some(block) = blocks.last_mut() else {
+                    return Err(error("instruction outside of a block".into()));
+                };
+                let (name, op) = parse_line(line).map_err(error)?;
+                block.lines.push(Line {
+                    line: n,
+                    name,
+                    op,
+                    loc: parse_loc(comment.trim()),
+                });
+            }
+        }
+        let ret = ret.ok_or_else(|| ParseError {
+            line: text.lines().count(),
+            message: "missing return type".into(),
+        })?;
+        // instructions are numbered in the order they are
+        // written in, which is the order they are built in
+        let mut names = Map::new();
+        for (n, line) in blocks.iter().flat_map(|b| b.lines.iter()).enumerate() {
+            if let Some(name) = line.name {
+                if names.insert(name, Instruction(n as u32)).is_some() {
+                    return Err(ParseError {
+                        line: line.line,
+                        message: format!("@{name} is defined twice"),
+                    });
+                }
+            }
+        }
+        let mut unit = Unit::new();
+        // recursion is typed by the return type
+        unit.retsig = Some(ret);
+        for b in blocks.iter() {
+            unit.new_block(&b.sig);
+        }
+        for (b, text) in blocks.iter().enumerate() {
+            check_terminator(text, blocks.len())?;
+            let mut cursor = unit.cursor(Block(b as u32));
+            for line in text.lines.iter() {
+                let resolve = |name: &str| names.get(name).copied();
+                cursor.set_loc(line.loc);
+                emit(&mut cursor, &line.op, resolve).map_err(|message| ParseError {
+                    line: line.line,
+                    message,
+                })?;
+            }
+        }
+        Ok((unit, ret))
+    }
+}
+
+// builds an instruction at the cursor, operands
+// are looked up by name as they are needed
+pub(crate) fn emit(
+    cursor: &mut Cursor,
+    op: &Op,
+    resolve: impl Fn(&str) -> Option<Instruction>,
+) -> Result<Instruction, String> {
+    let resolve = |name: &str| resolve(name).ok_or_else(|| format!("@{name} is not defined"));
+    let list = |names: &[&str]| {
+        names
+            .iter()
+            .map(|n| resolve(n))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok(match op {
+        Op::FetchArg(i) => cursor.fetch_arg(*i),
+        Op::IConst(n) => cursor.iconst(Type::Int32, *n),
+        Op::Binary(op, [a, b]) => {
+            let args = [resolve(a)?, resolve(b)?];
+            match *op {
+                "add" => cursor.add(args),
+                "sub" => cursor.sub(args),
+                "mul" => cursor.mul(args),
+                "shl" => cursor.shl(args),
+                "less" => cursor.less(args),
+                _ => cursor.more(args),
+            }
+        }
+        Op::Recur(args) => cursor.recurse(&list(args)?),
+        Op::Call(u, t, args) => cursor.call(*u, *t, &list(args)?),
+        Op::If(c) => cursor.do_if(resolve(c)?),
+        Op::Branch(Block::MAX, args) => cursor.ret(&list(args)?),
+        Op::Branch(b, args) => cursor.branch(*b, &list(args)?),
+    })
+}
+
+// blocks end in either a branch or an if and two branches,
+// which the rest of the crate relies upon
+fn check_terminator(text: &BlockText, blocks: usize) -> Result<(), ParseError> {
+    let is_term = |l: &Line| matches!(l.op, Op::If(_) | Op::Branch(..));
+    let start = text
+        .lines
+        .iter()
+        .position(is_term)
+        .unwrap_or(text.lines.len());
+    let term = &text.lines[start..];
+    let error = |line, message: &str| {
+        Err(ParseError {
+            line,
+            message: message.into(),
+        })
+    };
+    let shape = term
+        .iter()
+        .map(|l| matches!(l.op, Op::If(_)))
+        .collect::<Vec<_>>();
+    match shape[..] {
+        [false] | [true, false, false] => {}
+        [] => return error(text.line, "block is not terminated"),
+        _ => return error(term[0].line, "expected a branch or an if and two branches"),
+    }
+    for line in term {
+        if let Op::Branch(b, _) = line.op {
+            if b != Block::MAX && b.0 as usize >= blocks {
+                return error(line.line, &format!("block {b} does not exist"));
+            }
+        }
+    }
+    Ok(())
+}
+
+// `f0:12:5`, other comments are no locations
+pub(crate) fn parse_loc(s: &str) -> Option<SourceLoc> {
+    let mut parts = s.strip_prefix('f')?.split(':').map(|n| n.parse().ok());
+    let loc = SourceLoc {
+        file: parts.next()??,
+        line: parts.next()??,
+        column: parts.next()??,
+    };
+    parts.next().is_none().then_some(loc)
+}
+
+pub(crate) fn parse_type(t: &str) -> Result<Type, String> {
+    match t {
+        "int32" => Ok(Type::Int32),
+        "void" => Ok(Type::Void),
+        t => Err(format!("unknown type `{t}`")),
+    }
+}
+
+fn parse_index(s: &str, prefix: char) -> Result<u32, String> {
+    s.strip_prefix(prefix)
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| format!("expected `{prefix}` and a number, found `{s}`"))
+}
+
+// `b1(p0: int32, p1: int32):`
+pub(crate) fn parse_header(line: &str) -> Result<(Block, Vec<Type>), String> {
+    let line = line
+        .strip_suffix(':')
+        .ok_or("expected `:` after the block")?;
+    let (name, params) = line.split_once('(').ok_or("expected `(` after the block")?;
+    let params = params
+        .strip_suffix(')')
+        .ok_or("expected `)` after the parameters")?;
+    let block = Block(parse_index(name.trim(), 'b')?);
+    let mut sig = Vec::new();
+    for (n, param) in params
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .enumerate()
+    {
+        let (name, t) = param
+            .split_once(':')
+            .ok_or("expected `:` after the parameter")?;
+        if parse_index(name.trim(), 'p')? as usize != n {
+            return Err(format!("expected parameter p{n}, found {}", name.trim()));
+        }
+        sig.push(parse_type(t.trim())?);
+    }
+    Ok((block, sig))
+}
+
+fn parse_value(s: &str) -> Result<&str, String> {
+    match s.trim().strip_prefix('@') {
+        Some(name) if !name.is_empty() && !name.contains(char::is_whitespace) => Ok(name),
+        _ => Err(format!("expected a value, found `{}`", s.trim())),
+    }
+}
+
+// `[@a, @b]`
+fn parse_list(s: &str) -> Result<Vec<&str>, String> {
+    let s = s.trim();
+    let inner = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .ok_or_else(|| format!("expected a list of values, found `{s}`"))?;
+    if inner.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    inner.split(',').map(parse_value).collect()
+}
+
+// `@n = op args` or `: term args`
+pub(crate) fn parse_line(line: &str) -> Result<(Option<&str>, Op<'_>), String> {
+    let (name, rest) = match line.strip_prefix(':') {
+        Some(rest) => (None, rest.trim()),
+        None => {
+            let (name, rest) = line.split_once('=').ok_or("expected `=` or `:`")?;
+            (Some(parse_value(name)?), rest.trim())
+        }
+    };
+    let (op, args) = rest.split_once(' ').unwrap_or((rest, ""));
+    let op = match (name.is_some(), op) {
+        (true, "fetchArg") => Op::FetchArg(parse_index(args.trim(), 'p')? as usize),
+        (true, "const") => Op::IConst(
+            args.trim()
+                .parse()
+                .map_err(|_| format!("expected a number, found `{}`", args.trim()))?,
+        ),
+        (true, "add" | "sub" | "mul" | "shl" | "less" | "more") => {
+            let (a, b) = args.split_once(',').ok_or("expected two operands")?;
+            Op::Binary(op, [parse_value(a)?, parse_value(b)?])
+        }
+        (true, "recur") => Op::Recur(parse_list(args)?),
+        (true, "call") => {
+            let (t, rest) = args
+                .trim()
+                .split_once(' ')
+                .ok_or("expected a type, a unit and arguments")?;
+            let (unit, args) = rest
+                .trim()
+                .split_once(' ')
+                .ok_or("expected a unit and arguments")?;
+            Op::Call(
+                UnitId(parse_index(unit, 'u')?),
+                parse_type(t)?,
+                parse_list(args)?,
+            )
+        }
+        (false, "if") => Op::If(parse_value(args)?),
+        (false, "ret") => Op::Branch(Block::MAX, parse_list(args)?),
+        (false, "br") => {
+            let (block, args) = args
+                .trim()
+                .split_once(' ')
+                .ok_or("expected a block and arguments")?;
+            Op::Branch(Block(parse_index(block, 'b')?), parse_list(args)?)
+        }
+        (true, op) => return Err(format!("unknown instruction `{op}`")),
+        (false, op) => return Err(format!("unknown terminator `{op}`")),
+    };
+    Ok((name, op))
+}
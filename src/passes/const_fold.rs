@@ -0,0 +1,124 @@
+use crate::data::{Block, InstKind, Instruction, TermData, Unit};
+use crate::mutate::{Target, Terminator};
+use crate::passes::{Changes, Pass};
+
+/// Folds instructions on constants, simplifies
+/// algebraic identities and resolves ifs on
+/// constant conditions into plain branches.
+pub struct ConstFold;
+impl Pass for ConstFold {
+    fn name(&self) -> &'static str {
+        "const-fold"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.const_fold()
+    }
+}
+
+// what an instruction folds into
+enum Fold {
+    Const(isize),
+    Value(Instruction),
+}
+
+// arithmetic happens on Int32, so results wrap around
+fn wrap(n: isize) -> isize {
+    n as i32 as isize
+}
+
+// evaluates a binary instruction on constant operands
+pub(crate) fn eval(kind: &InstKind, x: isize, y: isize) -> Option<isize> {
+    let (x, y) = (wrap(x), wrap(y));
+    Some(match kind {
+        InstKind::Add(_) => wrap(x.wrapping_add(y)),
+        InstKind::Sub(_) => wrap(x.wrapping_sub(y)),
+        InstKind::Mul(_) => wrap(x.wrapping_mul(y)),
+        // only the low bits of the amount count, as on most targets
+        InstKind::Shl(_) => (x as i32).wrapping_shl(y as u32) as isize,
+        InstKind::Less(_) => (x < y) as isize,
+        InstKind::More(_) => (x > y) as isize,
+        _ => return None,
+    })
+}
+
+impl Unit {
+    pub(crate) fn const_fold(&mut self) -> Changes {
+        let mut changes = Changes::NONE;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 0..self.blocks.len() {
+                let block = Block(b as u32);
+                // folding only ever replaces the current instruction,
+                // so the list may be walked over a copy
+                for i in self.blocks[block].insts.clone() {
+                    match self.fold(self.instructions[i].kind) {
+                        Some(Fold::Const(n)) => self.rewrite(i, InstKind::IConst(n)),
+                        Some(Fold::Value(v)) => {
+                            self.replace_all_uses(i, v);
+                            self.remove(i);
+                        }
+                        None => continue,
+                    }
+                    changed = true;
+                    changes |= Changes::INSTS;
+                }
+                if self.fold_if(block) {
+                    changed = true;
+                    changes |= Changes::ALL;
+                }
+            }
+        }
+        changes
+    }
+    pub(crate) fn const_of(&self, inst: Instruction) -> Option<isize> {
+        match self.instructions[inst].kind {
+            InstKind::IConst(n) => Some(n),
+            _ => None,
+        }
+    }
+    fn fold(&self, kind: InstKind) -> Option<Fold> {
+        let [a, b] = match kind {
+            InstKind::Add(ops)
+            | InstKind::Sub(ops)
+            | InstKind::Mul(ops)
+            | InstKind::Shl(ops)
+            | InstKind::Less(ops)
+            | InstKind::More(ops) => ops,
+            _ => return None,
+        };
+        let (x, y) = (self.const_of(a), self.const_of(b));
+        if let (Some(x), Some(y)) = (x, y) {
+            return eval(&kind, x, y).map(Fold::Const);
+        }
+        match kind {
+            InstKind::Add(_) if x == Some(0) => Some(Fold::Value(b)),
+            InstKind::Add(_) | InstKind::Sub(_) if y == Some(0) => Some(Fold::Value(a)),
+            InstKind::Sub(_) | InstKind::Less(_) | InstKind::More(_) if a == b => {
+                Some(Fold::Const(0))
+            }
+            _ => None,
+        }
+    }
+    // turns `if` on a constant into a branch to the taken side
+    pub(crate) fn fold_if(&mut self, block: Block) -> bool {
+        let insts = &self.blocks[block].insts;
+        let start = self.blocks[block].term_start(&self.instructions);
+        let Some(InstKind::Terminator(TermData::DoIf(c))) =
+            insts.get(start).map(|i| self.instructions[*i].kind)
+        else {
+            return false;
+        };
+        let Some(cond) = self.const_of(c) else {
+            return false;
+        };
+        let taken = insts[start + if cond != 0 { 1 } else { 2 }];
+        let InstKind::Terminator(TermData::Branch(target, d)) = self.instructions[taken].kind
+        else {
+            return false;
+        };
+        let args = self.data[d].to_vec();
+        self.set_terminator(block, Terminator::Branch(Target(target, &args)));
+        true
+    }
+}
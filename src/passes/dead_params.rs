@@ -0,0 +1,105 @@
+use crate::data::{Block, InstKind, Instruction, TermData, Type, Unit};
+use crate::passes::{Changes, Pass};
+
+/// Removes block parameters that are never fetched, or
+/// whose fetches are unused, along with the arguments
+/// passed to them. The parameters of the entry block
+/// make up the signature of the unit and are kept.
+pub struct DeadParams;
+impl Pass for DeadParams {
+    fn name(&self) -> &'static str {
+        "dead-params"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.dead_params()
+    }
+}
+
+impl Unit {
+    pub(crate) fn dead_params(&mut self) -> Changes {
+        let mut changes = Changes::NONE;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 1..self.blocks.len() as u32 {
+                changed |= self.drop_dead_params(Block(b));
+            }
+            // dropped arguments may have been the last
+            // use of a parameter in the predecessor
+            if changed {
+                self.remove_unused();
+                changes = Changes::INSTS;
+            }
+        }
+        changes
+    }
+    fn drop_dead_params(&mut self, block: Block) -> bool {
+        let sig = self.signatures[self.blocks[block].signature].to_vec();
+        let fetches: Vec<(Instruction, usize)> = self.blocks[block]
+            .insts
+            .iter()
+            .filter_map(|i| match self.instructions[*i].kind {
+                InstKind::FetchArg(index) => Some((*i, index)),
+                _ => None,
+            })
+            .collect();
+        let live: Vec<bool> = (0..sig.len())
+            .map(|j| {
+                fetches
+                    .iter()
+                    .any(|(i, k)| *k == j && self.uses.is_used(*i))
+            })
+            .collect();
+        if live.iter().all(|l| *l) {
+            return false;
+        }
+        let new_sig: Vec<Type> = sig
+            .iter()
+            .zip(&live)
+            .filter(|(_, l)| **l)
+            .map(|(t, _)| *t)
+            .collect();
+        self.blocks[block].signature = self.signatures.push_slice(&new_sig);
+        // the new index of every parameter that is kept
+        let mut next = 0;
+        let index: Vec<Option<usize>> = live
+            .iter()
+            .map(|l| {
+                l.then(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect();
+        for (i, k) in fetches {
+            match index[k] {
+                None => self.remove(i),
+                Some(n) if n != k => self.rewrite(i, InstKind::FetchArg(n)),
+                Some(_) => {}
+            }
+        }
+        let branches: Vec<Instruction> = self
+            .blocks
+            .iter()
+            .flat_map(|b| b.insts.iter().copied())
+            .filter(|i| {
+                matches!(self.instructions[*i].kind,
+                    InstKind::Terminator(TermData::Branch(t, _)) if t == block)
+            })
+            .collect();
+        for br in branches {
+            let InstKind::Terminator(TermData::Branch(_, d)) = self.instructions[br].kind else {
+                unreachable!()
+            };
+            let args: Vec<Instruction> = self.data[d]
+                .iter()
+                .zip(&live)
+                .filter(|(_, l)| **l)
+                .map(|(a, _)| *a)
+                .collect();
+            let data = self.push_args(&args);
+            self.rewrite(br, InstKind::Terminator(TermData::Branch(block, data)));
+        }
+        true
+    }
+}
@@ -0,0 +1,87 @@
+use crate::data::{Block, InstKind, Instruction, Type, Unit};
+use crate::passes::{Changes, Pass};
+use std::collections::HashMap;
+
+/// Global value numbering.
+/// Replaces pure instructions by an equivalent one
+/// in a dominating position. Calls and terminators
+/// are never merged.
+pub struct Gvn;
+impl Pass for Gvn {
+    fn name(&self) -> &'static str {
+        "gvn"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.gvn()
+    }
+}
+
+// identifies the value an instruction computes
+type ValueKey = (InstKind, Type);
+
+impl Unit {
+    pub(crate) fn gvn(&mut self) -> Changes {
+        if self.blocks.len() == 0 {
+            return Changes::NONE;
+        }
+        // the cfg stays the same, so the tree is put back afterwards
+        self.dominators();
+        let doms = self.analyses.dominators.take().unwrap();
+        let mut table = HashMap::new();
+        let mut changes = Changes::NONE;
+        let mut stack = vec![(Block(0), false)];
+        let mut scopes = Vec::new();
+        // walk the dominator tree, values seen in a block
+        // are visible to every block it dominates
+        while let Some((block, done)) = stack.pop() {
+            if done {
+                for key in scopes.pop().unwrap_or_default() {
+                    table.remove(&key);
+                }
+                continue;
+            }
+            let mut scope = Vec::new();
+            for i in self.blocks[block].insts.clone() {
+                let Some(key) = self.value_key(i) else {
+                    continue;
+                };
+                match table.get(&key) {
+                    Some(&existing) => {
+                        self.replace_all_uses(i, existing);
+                        self.remove(i);
+                        changes = Changes::INSTS;
+                    }
+                    None => {
+                        table.insert(key, i);
+                        scope.push(key);
+                    }
+                }
+            }
+            scopes.push(scope);
+            stack.push((block, true));
+            for c in doms.children(block).iter().rev() {
+                stack.push((*c, false));
+            }
+        }
+        self.analyses.dominators = Some(doms);
+        changes
+    }
+    fn value_key(&self, inst: Instruction) -> Option<ValueKey> {
+        let inst = &self.instructions[inst];
+        let kind = match inst.kind {
+            // addition and multiplication commute,
+            // so operands are put in order
+            InstKind::Add([a, b]) if b < a => InstKind::Add([b, a]),
+            InstKind::Mul([a, b]) if b < a => InstKind::Mul([b, a]),
+            InstKind::IConst(_)
+            | InstKind::Add(_)
+            | InstKind::Sub(_)
+            | InstKind::Mul(_)
+            | InstKind::Shl(_)
+            | InstKind::Less(_)
+            | InstKind::More(_) => inst.kind,
+            _ => return None,
+        };
+        Some((kind, inst.typing))
+    }
+}
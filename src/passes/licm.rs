@@ -0,0 +1,112 @@
+use crate::data::{Block, InstKind, Instruction, Set, TermData, Unit};
+use crate::loops::Loop;
+use crate::passes::{Changes, Pass};
+
+/// Loop-invariant code motion.
+/// Hoists pure instructions whose operands are all defined
+/// outside of a loop into a new preheader block, which
+/// every entry into the loop passes through.
+pub struct Licm;
+impl Pass for Licm {
+    fn name(&self) -> &'static str {
+        "licm"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.licm()
+    }
+}
+
+impl Unit {
+    pub(crate) fn licm(&mut self) -> Changes {
+        let mut loops = self.loops().loops().to_vec();
+        let mut changes = Changes::NONE;
+        // inner loops first, so hoisted instructions
+        // may be hoisted again out of the outer ones
+        for l in (0..loops.len()).rev() {
+            // the entry block is entered by callers,
+            // nothing can be put in front of it
+            if loops[l].header == Block(0) {
+                continue;
+            }
+            let hoist = self.invariants(&loops[l]);
+            if hoist.is_empty() {
+                continue;
+            }
+            let pre = self.preheader(&loops[l]);
+            for i in hoist {
+                let from = self.instructions[i].block;
+                self.blocks[from].insts.retain(|x| *x != i);
+                let pos = self.blocks[pre].term_start(&self.instructions);
+                self.blocks[pre].insts.insert(pos, i);
+                self.instructions[i].block = pre;
+            }
+            // the preheader lies within every enclosing loop
+            let mut parent = loops[l].parent;
+            while let Some(p) = parent {
+                loops[p].body.insert(pre);
+                parent = loops[p].parent;
+            }
+            changes = Changes::ALL;
+        }
+        changes
+    }
+    // instructions of the loop not depending on it, in an
+    // order in which they can be hoisted one after another
+    fn invariants(&self, l: &Loop) -> Vec<Instruction> {
+        let mut out = Vec::new();
+        let mut hoisted = Set::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in l.body.iter() {
+                for &i in self.blocks[*b].insts.iter() {
+                    let kind = &self.instructions[i].kind;
+                    let pure = matches!(
+                        kind,
+                        InstKind::IConst(_)
+                            | InstKind::Add(_)
+                            | InstKind::Sub(_)
+                            | InstKind::Mul(_)
+                            | InstKind::Shl(_)
+                            | InstKind::Less(_)
+                            | InstKind::More(_)
+                    );
+                    if !pure || hoisted.contains(&i) {
+                        continue;
+                    }
+                    let invariant = kind.get_insts(&self.data).iter().all(|op| {
+                        !l.body.contains(&self.instructions[*op].block) || hoisted.contains(op)
+                    });
+                    if invariant {
+                        hoisted.insert(i);
+                        out.push(i);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        out
+    }
+    // a block forwarding its parameters to the header, every
+    // branch into the loop from outside is redirected to it
+    fn preheader(&mut self, l: &Loop) -> Block {
+        let sig = self.signatures[self.blocks[l.header].signature].to_vec();
+        let entries: Vec<Instruction> = (0..self.blocks.len() as u32)
+            .map(Block)
+            .filter(|b| !l.body.contains(b))
+            .flat_map(|b| self.terminator(b).to_vec())
+            .collect();
+        let pre = self.new_block(&sig).block();
+        for i in entries {
+            if let InstKind::Terminator(TermData::Branch(t, d)) = self.instructions[i].kind {
+                if t == l.header {
+                    self.instructions[i].kind = InstKind::Terminator(TermData::Branch(pre, d));
+                }
+            }
+        }
+        let mut cursor = self.cursor(pre);
+        let params: Vec<Instruction> = (0..sig.len()).map(|i| cursor.fetch_arg(i)).collect();
+        cursor.branch(l.header, &params);
+        pre
+    }
+}
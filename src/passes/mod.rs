@@ -0,0 +1,244 @@
+use crate::data::{OptLevel, Unit};
+use crate::dominators::Dominators;
+use crate::loops::Loops;
+
+pub(crate) mod const_fold;
+mod dead_params;
+mod gvn;
+mod licm;
+pub mod peephole;
+mod sccp;
+mod simplify_cfg;
+mod tail_rec;
+
+pub use const_fold::ConstFold;
+pub use dead_params::DeadParams;
+pub use gvn::Gvn;
+pub use licm::Licm;
+pub use peephole::Peephole;
+pub use sccp::Sccp;
+pub use simplify_cfg::SimplifyCfg;
+pub use tail_rec::TailRecursion;
+
+// Pass management
+//   Passes are run in order by a PassManager, reporting
+//   what they changed so cached analyses can be dropped
+
+/// What a pass changed about the unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Changes {
+    /// Blocks or the branches between them were changed.
+    pub cfg: bool,
+    /// Instructions were added, removed or rewritten.
+    pub insts: bool,
+}
+
+impl Changes {
+    pub const NONE: Self = Self {
+        cfg: false,
+        insts: false,
+    };
+    pub const INSTS: Self = Self {
+        cfg: false,
+        insts: true,
+    };
+    pub const ALL: Self = Self {
+        cfg: true,
+        insts: true,
+    };
+    pub fn any(self) -> bool {
+        self.cfg || self.insts
+    }
+}
+impl std::ops::BitOr for Changes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self {
+            cfg: self.cfg || rhs.cfg,
+            insts: self.insts || rhs.insts,
+        }
+    }
+}
+impl std::ops::BitOrAssign for Changes {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = *self | rhs;
+    }
+}
+
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    /// Runs the pass, analyses only fill the cache
+    /// and report no changes.
+    fn run(&mut self, unit: &mut Unit) -> Changes;
+}
+
+/// Analyses cached on the unit.
+#[derive(Default)]
+pub struct Analyses {
+    pub(crate) dominators: Option<Dominators>,
+    pub(crate) loops: Option<Loops>,
+    // whether `Unit::liveness` is up to date
+    pub(crate) liveness: bool,
+}
+
+impl Analyses {
+    pub(crate) fn invalidate(&mut self, changes: Changes) {
+        if changes.cfg {
+            self.dominators = None;
+            self.loops = None;
+        }
+        if changes.any() {
+            self.liveness = false;
+        }
+    }
+}
+
+impl Unit {
+    /// Drops the analyses made stale by the given changes.
+    pub fn invalidate(&mut self, changes: Changes) {
+        self.analyses.invalidate(changes);
+    }
+    /// The dominator tree, computed if not cached.
+    pub fn dominators(&mut self) -> &Dominators {
+        if self.analyses.dominators.is_none() {
+            self.analyses.dominators = Some(Dominators::compute(self));
+        }
+        self.analyses.dominators.as_ref().unwrap()
+    }
+    /// Recomputes `Unit::liveness` if it is stale.
+    pub fn update_liveness(&mut self) {
+        if !self.analyses.liveness {
+            self.annotate_liveness();
+            self.analyses.liveness = true;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The pipeline used by `Unit::finalize` for the given level.
+    pub fn preset(level: OptLevel) -> Self {
+        let mut out = Self::new();
+        match level {
+            OptLevel::O0 => {}
+            OptLevel::O1 => {
+                out.add(TailRecursion)
+                    .add(ConstFold)
+                    .add(Peephole::default())
+                    .add(SimplifyCfg)
+                    .add(DeadParams);
+            }
+            OptLevel::O2 => {
+                out.add(TailRecursion)
+                    .add(Sccp)
+                    .add(ConstFold)
+                    .add(Peephole::default())
+                    .add(SimplifyCfg)
+                    .add(DeadParams)
+                    .add(Gvn)
+                    .add(Licm);
+            }
+        }
+        out.add(RemoveUnused).add(Compact).add(Liveness);
+        out
+    }
+    pub fn add<P: Pass + 'static>(&mut self, pass: P) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+    /// Adds a builtin pass by its name, returning
+    /// false if there is no pass of that name.
+    pub fn add_named(&mut self, name: &str) -> bool {
+        match name {
+            "tail-recursion" => self.add(TailRecursion),
+            "sccp" => self.add(Sccp),
+            "const-fold" => self.add(ConstFold),
+            "peephole" => self.add(Peephole::default()),
+            "simplify-cfg" => self.add(SimplifyCfg),
+            "dead-params" => self.add(DeadParams),
+            "gvn" => self.add(Gvn),
+            "licm" => self.add(Licm),
+            "remove-unused" => self.add(RemoveUnused),
+            "compact" => self.add(Compact),
+            "liveness" => self.add(Liveness),
+            "dominators" => self.add(Dominance),
+            _ => return false,
+        };
+        true
+    }
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.passes.iter().map(|p| p.name())
+    }
+    /// Runs every pass in order, returning everything that changed.
+    pub fn run(&mut self, unit: &mut Unit) -> Changes {
+        let mut changes = Changes::NONE;
+        for pass in self.passes.iter_mut() {
+            let changed = pass.run(unit);
+            unit.invalidate(changed);
+            changes |= changed;
+        }
+        changes
+    }
+}
+
+// Builtin passes
+
+/// Tombstones instructions without uses.
+pub struct RemoveUnused;
+impl Pass for RemoveUnused {
+    fn name(&self) -> &'static str {
+        "remove-unused"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        match unit.remove_unused() {
+            true => Changes::INSTS,
+            false => Changes::NONE,
+        }
+    }
+}
+
+/// Drops tombstones, see `Unit::compact`.
+pub struct Compact;
+impl Pass for Compact {
+    fn name(&self) -> &'static str {
+        "compact"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        match unit.compact() {
+            true => Changes::INSTS,
+            false => Changes::NONE,
+        }
+    }
+}
+
+/// Computes `Unit::liveness`.
+pub struct Liveness;
+impl Pass for Liveness {
+    fn name(&self) -> &'static str {
+        "liveness"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.update_liveness();
+        Changes::NONE
+    }
+}
+
+/// Computes the dominator tree.
+pub struct Dominance;
+impl Pass for Dominance {
+    fn name(&self) -> &'static str {
+        "dominators"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.dominators();
+        Changes::NONE
+    }
+}
@@ -0,0 +1,334 @@
+use crate::data::{Block, InstKind, Instruction, TermData, Unit};
+use crate::passes::{Changes, Pass};
+
+// Peephole rules
+//   Rewrites of single instructions are written as a table of
+//   patterns over the operands and what the instruction turns
+//   into, the driver applies the first matching rule until no
+//   rule matches anywhere anymore. Comparisons deciding an if
+//   are moved right in front of it, for a code generator to
+//   fuse the two into a single compare and branch
+
+/// The binary instructions rules match on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Shl,
+    Less,
+    More,
+}
+
+/// Names an operand bound by a pattern, below `VARS`.
+pub type Var = usize;
+/// How many variables a rule may use.
+pub const VARS: usize = 4;
+
+/// Matches an operand, binding it to a variable.
+/// A variable bound twice only matches the same value.
+#[derive(Debug, Clone, Copy)]
+pub enum Pat {
+    Any(Var),
+    Const(Var),
+    /// A positive constant power of two.
+    PowerOfTwo(Var),
+    /// Exactly the given constant.
+    Is(isize),
+}
+
+/// An operand of the replacement.
+#[derive(Debug, Clone, Copy)]
+pub enum Expr {
+    Var(Var),
+    /// A new constant.
+    Lit(isize),
+    /// A new constant computed from a bound one.
+    Const(Var, fn(isize) -> isize),
+}
+
+/// What a matched instruction is replaced by.
+#[derive(Debug, Clone, Copy)]
+pub enum Rewrite {
+    Op(Op, [Expr; 2]),
+    Var(Var),
+    Const(isize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    pub name: &'static str,
+    pub op: Op,
+    /// Operands of commuting instructions are
+    /// also matched the other way around.
+    pub args: [Pat; 2],
+    pub into: Rewrite,
+}
+
+fn log2(n: isize) -> isize {
+    n.trailing_zeros() as isize
+}
+fn negate(n: isize) -> isize {
+    (n as i32).wrapping_neg() as isize
+}
+
+/// The rules run by `Peephole::default`.
+/// Earlier rules take precedence, no rule may undo another.
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: "mul-zero",
+        op: Op::Mul,
+        args: [Pat::Any(0), Pat::Is(0)],
+        into: Rewrite::Const(0),
+    },
+    Rule {
+        name: "mul-one",
+        op: Op::Mul,
+        args: [Pat::Any(0), Pat::Is(1)],
+        into: Rewrite::Var(0),
+    },
+    Rule {
+        name: "mul-pow2",
+        op: Op::Mul,
+        args: [Pat::Any(0), Pat::PowerOfTwo(1)],
+        into: Rewrite::Op(Op::Shl, [Expr::Var(0), Expr::Const(1, log2)]),
+    },
+    Rule {
+        name: "shl-zero",
+        op: Op::Shl,
+        args: [Pat::Any(0), Pat::Is(0)],
+        into: Rewrite::Var(0),
+    },
+    Rule {
+        name: "add-self",
+        op: Op::Add,
+        args: [Pat::Any(0), Pat::Any(0)],
+        into: Rewrite::Op(Op::Shl, [Expr::Var(0), Expr::Lit(1)]),
+    },
+    Rule {
+        name: "sub-const",
+        op: Op::Sub,
+        args: [Pat::Any(0), Pat::Const(1)],
+        into: Rewrite::Op(Op::Add, [Expr::Var(0), Expr::Const(1, negate)]),
+    },
+];
+
+/// Applies peephole rules until none matches.
+pub struct Peephole {
+    rules: Vec<Rule>,
+    /// Whether a comparison only used by an if in its
+    /// block is moved right in front of it.
+    pub fuse_branches: bool,
+}
+impl Peephole {
+    /// Panics if a rule uses a variable past `VARS` or one its
+    /// patterns don't bind, or computes a constant from a
+    /// variable not bound to one.
+    pub fn new(rules: Vec<Rule>) -> Self {
+        for rule in rules.iter() {
+            rule.check();
+        }
+        Self {
+            rules,
+            fuse_branches: true,
+        }
+    }
+}
+impl Default for Peephole {
+    fn default() -> Self {
+        Self::new(RULES.to_vec())
+    }
+}
+impl Pass for Peephole {
+    fn name(&self) -> &'static str {
+        "peephole"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.peephole(&self.rules, self.fuse_branches)
+    }
+}
+
+impl Rule {
+    fn check(&self) {
+        // whether each variable is bound, and to a constant
+        let mut bound = [None; VARS];
+        for pat in self.args {
+            let (v, constant) = match pat {
+                Pat::Any(v) => (v, false),
+                Pat::Const(v) | Pat::PowerOfTwo(v) => (v, true),
+                Pat::Is(_) => continue,
+            };
+            let slot = self.var(&mut bound, v);
+            *slot = Some(constant || *slot == Some(true));
+        }
+        let exprs = match self.into {
+            Rewrite::Op(_, exprs) => exprs,
+            Rewrite::Var(v) => [Expr::Var(v), Expr::Lit(0)],
+            Rewrite::Const(n) => [Expr::Lit(n), Expr::Lit(0)],
+        };
+        for expr in exprs {
+            match expr {
+                Expr::Var(v) if self.var(&mut bound, v).is_none() => {
+                    panic!(
+                        "Rule {} uses variable {}, which its patterns don't bind!",
+                        self.name, v
+                    )
+                }
+                Expr::Const(v, _) if *self.var(&mut bound, v) != Some(true) => panic!(
+                    "Rule {} computes from variable {}, which is not bound to a constant!",
+                    self.name, v
+                ),
+                _ => {}
+            }
+        }
+    }
+    fn var<'a>(&self, bound: &'a mut [Option<bool>; VARS], v: Var) -> &'a mut Option<bool> {
+        match bound.get_mut(v) {
+            Some(slot) => slot,
+            None => panic!(
+                "Rule {} uses variable {}, but only {} exist!",
+                self.name, v, VARS
+            ),
+        }
+    }
+}
+
+impl Op {
+    fn of(kind: &InstKind) -> Option<(Self, [Instruction; 2])> {
+        Some(match *kind {
+            InstKind::Add(a) => (Self::Add, a),
+            InstKind::Sub(a) => (Self::Sub, a),
+            InstKind::Mul(a) => (Self::Mul, a),
+            InstKind::Shl(a) => (Self::Shl, a),
+            InstKind::Less(a) => (Self::Less, a),
+            InstKind::More(a) => (Self::More, a),
+            _ => return None,
+        })
+    }
+    fn build(self, args: [Instruction; 2]) -> InstKind {
+        match self {
+            Self::Add => InstKind::Add(args),
+            Self::Sub => InstKind::Sub(args),
+            Self::Mul => InstKind::Mul(args),
+            Self::Shl => InstKind::Shl(args),
+            Self::Less => InstKind::Less(args),
+            Self::More => InstKind::More(args),
+        }
+    }
+    fn commutes(self) -> bool {
+        matches!(self, Self::Add | Self::Mul)
+    }
+}
+
+// what the variables of a rule are bound to,
+// along with the value of bound constants
+type Bindings = [Option<(Instruction, Option<isize>)>; VARS];
+
+impl Unit {
+    pub(crate) fn peephole(&mut self, rules: &[Rule], fuse_branches: bool) -> Changes {
+        let mut changes = Changes::NONE;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 0..self.blocks.len() {
+                let block = Block(b as u32);
+                for i in self.blocks[block].insts.clone() {
+                    let matched = rules
+                        .iter()
+                        .find_map(|r| self.match_rule(r, i).map(|bound| (r, bound)));
+                    if let Some((rule, bound)) = matched {
+                        self.apply_rule(i, rule, &bound);
+                        changed = true;
+                        changes = Changes::INSTS;
+                    }
+                }
+                if fuse_branches && self.fuse_branch(block) {
+                    changes = Changes::INSTS;
+                }
+            }
+        }
+        changes
+    }
+    // moves the comparison an if decides on right in front of it,
+    // given nothing else uses it and it is from the same block
+    fn fuse_branch(&mut self, block: Block) -> bool {
+        let insts = &self.blocks[block].insts;
+        let start = self.blocks[block].term_start(&self.instructions);
+        let Some(&term) = insts.get(start) else {
+            return false;
+        };
+        let InstKind::Terminator(TermData::DoIf(c)) = self.instructions[term].kind else {
+            return false;
+        };
+        let fusable = matches!(
+            self.instructions[c].kind,
+            InstKind::Less(_) | InstKind::More(_)
+        ) && self.instructions[c].block == block
+            && self.users(c).all(|u| u == term);
+        if !fusable || start == 0 || insts[start - 1] == c {
+            return false;
+        }
+        let insts = &mut self.blocks[block].insts;
+        insts.retain(|i| *i != c);
+        insts.insert(start - 1, c);
+        true
+    }
+    fn match_rule(&self, rule: &Rule, inst: Instruction) -> Option<Bindings> {
+        let (op, [a, b]) = Op::of(&self.instructions[inst].kind)?;
+        if op != rule.op {
+            return None;
+        }
+        let mut orders = vec![[a, b]];
+        if op.commutes() {
+            orders.push([b, a]);
+        }
+        orders.into_iter().find_map(|args| {
+            let mut bound = [None; VARS];
+            rule.args
+                .iter()
+                .zip(args)
+                .all(|(pat, arg)| self.bind(*pat, arg, &mut bound))
+                .then_some(bound)
+        })
+    }
+    fn bind(&self, pat: Pat, arg: Instruction, bound: &mut Bindings) -> bool {
+        let value = self.const_of(arg);
+        let var = match pat {
+            Pat::Is(n) => return value == Some(n),
+            Pat::Any(v) => v,
+            Pat::Const(v) if value.is_some() => v,
+            Pat::PowerOfTwo(v) if value.is_some_and(|n| n > 0 && n.count_ones() == 1) => v,
+            _ => return false,
+        };
+        match bound[var] {
+            Some((prev, _)) => prev == arg,
+            None => {
+                bound[var] = Some((arg, value));
+                true
+            }
+        }
+    }
+    fn apply_rule(&mut self, inst: Instruction, rule: &Rule, bound: &Bindings) {
+        let var = |v: Var| bound[v].expect("Rule uses an unbound variable!");
+        match rule.into {
+            Rewrite::Var(v) => {
+                self.replace_all_uses(inst, var(v).0);
+                self.remove(inst);
+            }
+            Rewrite::Const(n) => self.rewrite(inst, InstKind::IConst(n)),
+            Rewrite::Op(op, exprs) => {
+                // constants take the type of what they compute
+                let t = self.instructions[inst].typing;
+                let args = exprs.map(|e| match e {
+                    Expr::Var(v) => var(v).0,
+                    Expr::Lit(n) => self.insert_before(inst, InstKind::IConst(n), t),
+                    Expr::Const(v, f) => {
+                        let n = var(v).1.expect("Rule computes from a non-constant!");
+                        self.insert_before(inst, InstKind::IConst(f(n)), t)
+                    }
+                });
+                self.rewrite(inst, op.build(args));
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::data::{Block, InstKind, Instruction, Map, Set, TermData, Unit};
+use crate::passes::const_fold::eval;
+use crate::passes::{Changes, Pass};
+
+/// Sparse conditional constant propagation.
+/// Propagates constants through branch arguments into the
+/// parameters of their targets, while only considering
+/// branches that may actually be taken. Blocks that are
+/// never reached are removed.
+pub struct Sccp;
+impl Pass for Sccp {
+    fn name(&self) -> &'static str {
+        "sccp"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.sccp()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lattice {
+    // no value seen yet
+    Top,
+    Const(isize),
+    // may hold different values
+    Bottom,
+}
+
+impl Lattice {
+    fn meet(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Top, x) | (x, Self::Top) => x,
+            (Self::Const(a), Self::Const(b)) if a == b => self,
+            _ => Self::Bottom,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    values: Map<Instruction, Lattice>,
+    blocks: Set<Block>,
+    // branch instructions that may be taken
+    edges: Set<Instruction>,
+}
+
+impl State {
+    fn get(&self, inst: Instruction) -> Lattice {
+        self.values.get(&inst).copied().unwrap_or(Lattice::Top)
+    }
+    // lowers the value, returning whether it changed
+    fn lower(&mut self, inst: Instruction, value: Lattice) -> bool {
+        let old = self.get(inst);
+        let new = old.meet(value);
+        self.values.insert(inst, new);
+        old != new
+    }
+}
+
+impl Unit {
+    pub(crate) fn sccp(&mut self) -> Changes {
+        let state = self.sccp_solve();
+        let mut changes = Changes::NONE;
+        for b in state.blocks.iter() {
+            for i in self.blocks[*b].insts.clone() {
+                let kind = self.instructions[i].kind;
+                if matches!(kind, InstKind::IConst(_) | InstKind::Terminator(_)) {
+                    continue;
+                }
+                if let Lattice::Const(n) = state.get(i) {
+                    self.rewrite(i, InstKind::IConst(n));
+                    changes |= Changes::INSTS;
+                }
+            }
+            if self.fold_if(*b) {
+                changes |= Changes::ALL;
+            }
+        }
+        // branches that are never taken are gone now,
+        // so whatever can't be reached anymore is dead
+        if self.remove_unreachable() {
+            changes |= Changes::ALL;
+        }
+        changes
+    }
+    fn sccp_solve(&self) -> State {
+        let mut state = State::default();
+        if self.blocks.len() == 0 {
+            return state;
+        }
+        state.blocks.insert(Block(0));
+        // branches are needed to evaluate block parameters
+        let mut incoming = Map::<Block, Vec<Instruction>>::new();
+        for blockdata in self.blocks.iter() {
+            for &i in blockdata.insts.iter() {
+                if let InstKind::Terminator(TermData::Branch(b, _)) = self.instructions[i].kind {
+                    incoming.entry(b).or_default().push(i);
+                }
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in state.blocks.clone() {
+                for &i in self.blocks[b].insts.iter() {
+                    let value = self.sccp_eval(i, b, &state, &incoming);
+                    changed |= state.lower(i, value);
+                }
+                for edge in self.sccp_edges(b, &state) {
+                    changed |= state.edges.insert(edge);
+                    if let InstKind::Terminator(TermData::Branch(t, _)) =
+                        self.instructions[edge].kind
+                    {
+                        if t != Block::MAX {
+                            changed |= state.blocks.insert(t);
+                        }
+                    }
+                }
+            }
+        }
+        state
+    }
+    fn sccp_eval(
+        &self,
+        i: Instruction,
+        block: Block,
+        state: &State,
+        incoming: &Map<Block, Vec<Instruction>>,
+    ) -> Lattice {
+        let kind = self.instructions[i].kind;
+        match kind {
+            InstKind::IConst(n) => Lattice::Const(n),
+            // the entry block is fed by callers
+            InstKind::FetchArg(_) if block == Block(0) => Lattice::Bottom,
+            InstKind::FetchArg(index) => {
+                let mut out = Lattice::Top;
+                for e in incoming.get(&block).into_iter().flatten() {
+                    if !state.edges.contains(e) {
+                        continue;
+                    }
+                    let InstKind::Terminator(TermData::Branch(_, d)) = self.instructions[*e].kind
+                    else {
+                        unreachable!()
+                    };
+                    out = out.meet(state.get(self.data[d][index]));
+                }
+                out
+            }
+            InstKind::Add([a, b])
+            | InstKind::Sub([a, b])
+            | InstKind::Mul([a, b])
+            | InstKind::Shl([a, b])
+            | InstKind::Less([a, b])
+            | InstKind::More([a, b]) => match (state.get(a), state.get(b)) {
+                (Lattice::Const(x), Lattice::Const(y)) => {
+                    eval(&kind, x, y).map_or(Lattice::Bottom, Lattice::Const)
+                }
+                (Lattice::Bottom, _) | (_, Lattice::Bottom) => Lattice::Bottom,
+                _ => Lattice::Top,
+            },
+            InstKind::Recur(_) | InstKind::Call(..) => Lattice::Bottom,
+            InstKind::Tombstone | InstKind::Terminator(_) => Lattice::Top,
+        }
+    }
+    // the branches of a block that may be taken
+    fn sccp_edges(&self, block: Block, state: &State) -> Vec<Instruction> {
+        let insts = &self.blocks[block].insts;
+        let start = self.blocks[block].term_start(&self.instructions);
+        match insts.get(start).map(|i| self.instructions[*i].kind) {
+            Some(InstKind::Terminator(TermData::DoIf(c))) => match state.get(c) {
+                Lattice::Top => vec![],
+                Lattice::Const(0) => vec![insts[start + 2]],
+                Lattice::Const(_) => vec![insts[start + 1]],
+                Lattice::Bottom => vec![insts[start + 1], insts[start + 2]],
+            },
+            Some(_) => vec![insts[start]],
+            None => vec![],
+        }
+    }
+}
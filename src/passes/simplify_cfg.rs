@@ -0,0 +1,152 @@
+use crate::data::{Block, InstKind, Instruction, Set, TermData, Unit};
+use crate::passes::{Changes, Pass};
+
+/// Simplifies the control flow graph.
+/// Merges blocks into their only predecessor, threads
+/// branches through blocks that only forward their
+/// parameters and deletes unreachable blocks.
+pub struct SimplifyCfg;
+impl Pass for SimplifyCfg {
+    fn name(&self) -> &'static str {
+        "simplify-cfg"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.simplify_cfg()
+    }
+}
+
+impl Unit {
+    pub(crate) fn simplify_cfg(&mut self) -> Changes {
+        let mut changes = Changes::NONE;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 1..self.blocks.len() as u32 {
+                changed |= self.thread_through(Block(b));
+                changed |= self.merge_into_pred(Block(b));
+            }
+            changed |= self.remove_unreachable();
+            if changed {
+                changes = Changes::ALL;
+            }
+        }
+        changes
+    }
+    // the target and arguments of a lone branch
+    fn lone_branch(&self, block: Block) -> Option<(Instruction, Block, Vec<Instruction>)> {
+        match self.terminator(block) {
+            [i] => match self.instructions[*i].kind {
+                InstKind::Terminator(TermData::Branch(t, d)) => {
+                    Some((*i, t, self.data[d].to_vec()))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    // the branches in other blocks targeting the given one
+    fn branches_to(&self, block: Block) -> Vec<Instruction> {
+        let mut out = Vec::new();
+        for (b, blockdata) in self.blocks.iter().enumerate() {
+            if b as u32 == block.0 {
+                continue;
+            }
+            for &i in blockdata.insts.iter() {
+                if let InstKind::Terminator(TermData::Branch(t, _)) = self.instructions[i].kind {
+                    if t == block {
+                        out.push(i);
+                    }
+                }
+            }
+        }
+        out
+    }
+    // the parameters of a forwarding block may only
+    // be used by its branch, as the block is skipped
+    fn is_forwarding(&self, block: Block) -> bool {
+        if block == Block(0) || block == Block::MAX {
+            return false;
+        }
+        let Some((br, _, _)) = self.lone_branch(block) else {
+            return false;
+        };
+        self.blocks[block]
+            .insts
+            .iter()
+            .all(|i| match self.instructions[*i].kind {
+                InstKind::FetchArg(_) => self.users(*i).all(|u| u == br),
+                InstKind::Terminator(_) => true,
+                _ => false,
+            })
+    }
+    // a block consisting of nothing but fetching its parameters
+    // and branching on is skipped by branching to its target directly
+    fn thread_through(&mut self, block: Block) -> bool {
+        if !self.is_forwarding(block) {
+            return false;
+        }
+        let Some((_, target, args)) = self.lone_branch(block) else {
+            return false;
+        };
+        // chains of forwarding blocks are threaded from the back,
+        // which also keeps cycles of them from being threaded forever
+        let preds = self.branches_to(block);
+        if target == block || self.is_forwarding(target) || preds.is_empty() {
+            return false;
+        }
+        for p in preds {
+            let InstKind::Terminator(TermData::Branch(_, d)) = self.instructions[p].kind else {
+                unreachable!()
+            };
+            let incoming = self.data[d].to_vec();
+            let forwarded: Vec<Instruction> = args
+                .iter()
+                .map(|a| match self.instructions[*a].kind {
+                    InstKind::FetchArg(i) if self.instructions[*a].block == block => incoming[i],
+                    _ => *a,
+                })
+                .collect();
+            let data = self.push_args(&forwarded);
+            self.rewrite(p, InstKind::Terminator(TermData::Branch(target, data)));
+        }
+        true
+    }
+    // a block whose only predecessor branches nowhere else
+    // is appended to that predecessor
+    fn merge_into_pred(&mut self, block: Block) -> bool {
+        let preds = self.predecessors();
+        let [pred] = preds.get(&block).map_or(&[][..], |p| p) else {
+            return false;
+        };
+        let pred = *pred;
+        let Some((br, target, args)) = self.lone_branch(pred) else {
+            return false;
+        };
+        if target != block || pred == block {
+            return false;
+        }
+        self.remove(br);
+        for i in std::mem::take(&mut self.blocks[block].insts) {
+            if let InstKind::FetchArg(index) = self.instructions[i].kind {
+                self.replace_all_uses(i, args[index]);
+                self.tombstone(i);
+            } else {
+                self.instructions[i].block = pred;
+                self.blocks[pred].insts.push(i);
+            }
+        }
+        true
+    }
+    pub(crate) fn remove_unreachable(&mut self) -> bool {
+        let live: Set<Block> = self.reverse_postorder().into_iter().collect();
+        let dead: Set<Block> = (0..self.blocks.len() as u32)
+            .map(Block)
+            .filter(|b| !live.contains(b))
+            .collect();
+        if dead.is_empty() {
+            return false;
+        }
+        self.remove_blocks(&dead);
+        true
+    }
+}
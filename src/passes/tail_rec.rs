@@ -0,0 +1,75 @@
+use crate::data::{Block, InstKind, Instruction, TermData, Unit};
+use crate::passes::{Changes, Pass};
+
+/// Turns recursion in tail position into a loop.
+/// The body of the entry block is moved into a new loop
+/// header taking the parameters of the unit, every
+/// `ret [recur args]` then becomes a branch to the header.
+pub struct TailRecursion;
+impl Pass for TailRecursion {
+    fn name(&self) -> &'static str {
+        "tail-recursion"
+    }
+    fn run(&mut self, unit: &mut Unit) -> Changes {
+        unit.tail_recursion()
+    }
+}
+
+impl Unit {
+    pub(crate) fn tail_recursion(&mut self) -> Changes {
+        let calls = self.tail_calls();
+        if calls.is_empty() {
+            return Changes::NONE;
+        }
+        let sig = self.signatures[self.blocks[Block(0)].signature].to_vec();
+        let header = self.new_block(&sig).block();
+        // the entry block only fetches the parameters
+        // and passes them on to the header
+        let body = std::mem::take(&mut self.blocks[Block(0)].insts);
+        for &i in body.iter() {
+            self.instructions[i].block = header;
+        }
+        self.blocks[header].insts = body;
+        for inst in self.instructions.iter_mut() {
+            if let InstKind::Terminator(TermData::Branch(Block(0), d)) = inst.kind {
+                inst.kind = InstKind::Terminator(TermData::Branch(header, d));
+            }
+        }
+        let mut cursor = self.cursor(Block(0));
+        let params: Vec<Instruction> = (0..sig.len()).map(|i| cursor.fetch_arg(i)).collect();
+        cursor.branch(header, &params);
+        for (ret, recur) in calls {
+            let InstKind::Recur(args) = self.instructions[recur].kind else {
+                unreachable!()
+            };
+            let args = self.data[args].to_vec();
+            let data = self.push_args(&args);
+            self.rewrite(ret, InstKind::Terminator(TermData::Branch(header, data)));
+            self.remove(recur);
+        }
+        Changes::ALL
+    }
+    // returns that directly return the result of a recursion,
+    // along with that recursion
+    fn tail_calls(&self) -> Vec<(Instruction, Instruction)> {
+        let mut out = Vec::new();
+        for blockdata in self.blocks.iter() {
+            for &ret in blockdata.insts.iter() {
+                let InstKind::Terminator(TermData::Branch(Block::MAX, d)) =
+                    self.instructions[ret].kind
+                else {
+                    continue;
+                };
+                let &[recur] = &self.data[d] else {
+                    continue;
+                };
+                if matches!(self.instructions[recur].kind, InstKind::Recur(_))
+                    && self.users(recur).eq([ret])
+                {
+                    out.push((ret, recur));
+                }
+            }
+        }
+        out
+    }
+}
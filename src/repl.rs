@@ -0,0 +1,191 @@
+use crate::data::{Block, InstKind, Instruction, Map, TermData, Type, Unit};
+use crate::parse::{emit, parse_header, parse_line, parse_loc, parse_type, Op};
+
+// REPL
+//   Builds a unit a line at a time through a `Cursor`, checking
+//   every line as it is entered. Unlike the text format values
+//   and blocks have to exist before they are referred to, an
+//   earlier block is reentered by writing its header again.
+//   Everything needing a finalized unit works on a copy.
+
+const HELP: &str = "\
+enter lines of the text format:
+    b0(p0: int32):          start a block, or go back to it
+    @x = add @a, @b         append an instruction to the block
+    : br b1 [@x]            terminate the block
+    return(int32)           set the return type
+commands:
+    .print                  print the unit
+    .canonical              print the unit with values in order
+    .check                  verify the whole unit
+    .liveness               print the lifetimes of values
+    .dot                    print the control flow graph
+    .run 1 2 ..             run the unit with the interpreter
+    .undo                   drop the last line entered
+    .reset                  start over
+    .help                   print this
+    .quit                   leave";
+
+/// An interactive session building a single unit.
+#[derive(Default)]
+pub struct Repl {
+    unit: Unit,
+    names: Map<String, Instruction>,
+    block: Option<Block>,
+    ret: Option<Type>,
+    // accepted lines, replayed on undo
+    history: Vec<String>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Handles a line of input, returning what to print.
+    /// Lines with errors are not applied to the unit.
+    pub fn input(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim_start_matches('-').trim_start_matches('|').trim();
+        if let Some(command) = line.strip_prefix('.') {
+            return self.command(command);
+        }
+        if line.split("//").next().unwrap().trim().is_empty() {
+            return Ok(String::new());
+        }
+        let out = self.enter(line)?;
+        self.history.push(line.into());
+        Ok(out)
+    }
+    fn command(&mut self, command: &str) -> Result<String, String> {
+        let (command, args) = command.split_once(' ').unwrap_or((command, ""));
+        Ok(match command {
+            "help" => HELP.into(),
+            "print" => self.unit.human_format(),
+            "canonical" => self.unit.canonical_format(),
+            "check" => {
+                self.finalized()?;
+                "ok".into()
+            }
+            "liveness" => self.finalized()?.lifetime_table(),
+            "dot" => self.finalized()?.to_dot(),
+            "run" => {
+                let args = args
+                    .split_whitespace()
+                    .map(|n| n.parse().map_err(|_| format!("`{n}` is not a number")))
+                    .collect::<Result<Vec<isize>, _>>()?;
+                match self.finalized()?.run(&args).map_err(|e| e.to_string())? {
+                    Some(n) => n.to_string(),
+                    None => "returned".into(),
+                }
+            }
+            "undo" => {
+                let mut history = std::mem::take(&mut self.history);
+                history.pop().ok_or("nothing to undo")?;
+                *self = Self::new();
+                for line in history {
+                    self.input(&line)?;
+                }
+                String::new()
+            }
+            "reset" => {
+                *self = Self::new();
+                String::new()
+            }
+            command => return Err(format!("unknown command `.{command}`, see `.help`")),
+        })
+    }
+    fn enter(&mut self, line: &str) -> Result<String, String> {
+        let (line, comment) = line.split_once("//").unwrap_or((line, ""));
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("return(") {
+            let t = rest.strip_suffix(')').ok_or("expected `)`")?;
+            let t = match t.trim() {
+                "" => Type::Void,
+                t => parse_type(t)?,
+            };
+            self.ret = Some(t);
+            self.unit.retsig = Some(t);
+            return Ok(String::new());
+        }
+        if line.starts_with('b') {
+            let (b, sig) = parse_header(line)?;
+            match self.unit.blocks.get(b) {
+                Some(data) if self.unit.signatures[data.signature] != sig[..] => {
+                    return Err(format!("{b} was declared with another signature"));
+                }
+                Some(_) => {}
+                None if b.0 as usize == self.unit.blocks.len() => {
+                    self.unit.new_block(&sig);
+                }
+                None => {
+                    let next = Block(self.unit.blocks.len() as u32);
+                    return Err(format!("expected block {next}, found {b}"));
+                }
+            }
+            self.block = Some(b);
+            return Ok(String::new());
+        }
+        let block = self
+            .block
+            .ok_or("enter a block first, like `b0(p0: int32):`")?;
+        let (name, op) = parse_line(line)?;
+        if let Some(name) = name {
+            if self.names.contains_key(name) {
+                return Err(format!("@{name} is defined twice"));
+            }
+        }
+        if matches!(op, Op::Recur(_)) && self.ret.is_none() {
+            return Err("recursion needs the return type, enter it first".into());
+        }
+        self.check_shape(block, &op)?;
+        let names = &self.names;
+        let mut cursor = self.unit.cursor(block);
+        cursor.set_loc(parse_loc(comment.trim()));
+        let inst = emit(&mut cursor, &op, |n| names.get(n).copied())?;
+        // errors elsewhere are reported once the unit is checked as a whole
+        if let Err(errors) = self.unit.type_check() {
+            let errors: Vec<String> = errors
+                .iter()
+                .filter(|e| e.inst == inst)
+                .map(|e| e.to_string())
+                .collect();
+            if !errors.is_empty() {
+                self.unit.remove(inst);
+                return Err(errors.join("\n"));
+            }
+        }
+        Ok(match name {
+            Some(name) => {
+                self.names.insert(name.into(), inst);
+                format!("@{name} = {inst}")
+            }
+            None => String::new(),
+        })
+    }
+    // instructions may only be appended until the block is
+    // terminated, either by a branch or an if and two branches
+    fn check_shape(&self, block: Block, op: &Op) -> Result<(), String> {
+        let is_if = |i: &Instruction| {
+            matches!(
+                self.unit.instructions[*i].kind,
+                InstKind::Terminator(TermData::DoIf(_))
+            )
+        };
+        let term = self.unit.terminator(block);
+        let is_branch = matches!(op, Op::Branch(..));
+        match term {
+            [] => Ok(()),
+            [i] | [i, _] if is_if(i) && is_branch => Ok(()),
+            [i] | [i, _] if is_if(i) => Err("an if is followed by two branches".into()),
+            _ => Err(format!("{block} is already terminated")),
+        }
+    }
+    // a copy of the unit passing every check `finalize` makes,
+    // copied through the binary format which checks them and
+    // keeps instruction ids, unlike the passes run by `finalize`
+    fn finalized(&self) -> Result<Unit, String> {
+        if self.ret.is_none() {
+            return Err("no return type yet, enter one like `return(int32)`".into());
+        }
+        Unit::from_bytes(&self.unit.to_bytes()).map_err(|e| e.to_string())
+    }
+}
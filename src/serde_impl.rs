@@ -0,0 +1,72 @@
+use crate::data::{
+    Block, BlockData, DataPart, InstData, Instruction, Settings, SignaturePart, Type, Unit,
+};
+use crate::util::KeyVec;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Serde support
+//   Units are written as their containers, analyses and uses
+//   are rebuilt when reading them back, after checking them
+//   the same way as the binary format does
+
+impl<K, T: Serialize> Serialize for KeyVec<K, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+impl<'de, K, T: Deserialize<'de>> Deserialize<'de> for KeyVec<K, T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+#[derive(Serialize)]
+struct UnitRef<'a> {
+    settings: &'a Settings,
+    retsig: Option<Type>,
+    signatures: &'a KeyVec<SignaturePart, Type>,
+    data: &'a KeyVec<DataPart, Instruction>,
+    instructions: &'a KeyVec<Instruction, InstData>,
+    blocks: &'a KeyVec<Block, BlockData>,
+}
+#[derive(Deserialize)]
+struct UnitData {
+    settings: Settings,
+    retsig: Option<Type>,
+    signatures: KeyVec<SignaturePart, Type>,
+    data: KeyVec<DataPart, Instruction>,
+    instructions: KeyVec<Instruction, InstData>,
+    blocks: KeyVec<Block, BlockData>,
+}
+
+impl Serialize for Unit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        UnitRef {
+            settings: &self.settings,
+            retsig: self.retsig,
+            signatures: &self.signatures,
+            data: &self.data,
+            instructions: &self.instructions,
+            blocks: &self.blocks,
+        }
+        .serialize(serializer)
+    }
+}
+impl<'de> Deserialize<'de> for Unit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = UnitData::deserialize(deserializer)?;
+        let mut unit = Unit {
+            settings: data.settings,
+            retsig: data.retsig,
+            signatures: data.signatures,
+            data: data.data,
+            instructions: data.instructions,
+            blocks: data.blocks,
+            ..Unit::new()
+        };
+        unit.restore()
+            .map_err(|e| D::Error::custom(format!("invalid unit: {e}")))?;
+        Ok(unit)
+    }
+}
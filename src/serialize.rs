@@ -0,0 +1,526 @@
+use crate::data::{
+    Block, BlockData, DataPart, InstData, InstKind, Instruction, OptLevel, Settings, SignaturePart,
+    SourceLoc, TermData, Type, Unit, UnitId,
+};
+use crate::module::Module;
+use crate::typecheck::TypeError;
+use crate::util::KeyVec;
+
+// Binary format
+//   A unit is written as its header followed by one flat section
+//   per container, every section starts with its length. All
+//   numbers are little endian, indices are written as u32.
+//
+//   unit:         magic "RDXU", version, body
+//   module:       magic "RDXM", version, unit count, bodies
+//   body:         volatile u8, opt level u8, return type u8,
+//                 signatures, data, instructions, blocks
+//   signatures:   len, types u8
+//   data:         len, instructions
+//   instructions: len, (block, type u8, kind tag u8, operands, loc)
+//   loc:          0u8, or 1u8 followed by file, line and column
+//   blocks:       len, (signature start, end, len, instructions)
+//
+//   Liveness is not written, it is recomputed for finalized units
+
+const UNIT_MAGIC: [u8; 4] = *b"RDXU";
+const MODULE_MAGIC: [u8; 4] = *b"RDXM";
+/// Version of the binary format written by this crate.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Why decoding a unit or module failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input does not start with the expected magic number.
+    Magic,
+    /// The input was written in an unsupported version of the format.
+    Version(u32),
+    /// The input ends in the middle of a section.
+    UnexpectedEnd,
+    /// The input goes on after the last section.
+    TrailingBytes(usize),
+    /// A type, opt level, instruction kind or location has an unknown tag.
+    Tag { what: &'static str, tag: u8 },
+    /// An index points past the end of the section it addresses.
+    OutOfRange {
+        what: &'static str,
+        index: u32,
+        len: usize,
+    },
+    /// An instruction other than a tombstone is not listed
+    /// exactly once, in the block it belongs to.
+    Misplaced(Instruction),
+    /// A block of a finalized unit does not end in a branch
+    /// or in an if and two branches.
+    Unterminated(Block),
+    /// A finalized unit does not type check.
+    Type(Vec<TypeError>),
+    /// An operand of a finalized unit is not defined
+    /// on every path leading to its use.
+    UndefinedUse {
+        inst: Instruction,
+        operand: Instruction,
+    },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Magic => write!(f, "not a radix unit or module"),
+            DecodeError::Version(v) => {
+                write!(
+                    f,
+                    "format version {v} is not supported, expected {FORMAT_VERSION}"
+                )
+            }
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::TrailingBytes(n) => write!(f, "{n} bytes after the end of input"),
+            DecodeError::Tag { what, tag } => write!(f, "unknown {what} tag {tag}"),
+            DecodeError::OutOfRange { what, index, len } => {
+                write!(f, "{what} {index} out of range for {len}")
+            }
+            DecodeError::Misplaced(i) => write!(f, "{i} is not listed once in its block"),
+            DecodeError::Unterminated(b) => write!(f, "{b} is not terminated"),
+            DecodeError::Type(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            DecodeError::UndefinedUse { inst, operand } => {
+                write!(f, "{inst} uses {operand} which is not always defined")
+            }
+        }
+    }
+}
+impl std::error::Error for DecodeError {}
+
+impl Unit {
+    /// Encodes the unit in the binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(&UNIT_MAGIC);
+        w.u32(FORMAT_VERSION);
+        w.unit(self);
+        w.0
+    }
+    /// Decodes a unit written by `Unit::to_bytes`, checking
+    /// every index against the section it points into and
+    /// finalized units like `Unit::finalize` does.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Unit, DecodeError> {
+        let mut r = Reader::new(bytes, UNIT_MAGIC)?;
+        let unit = r.unit()?;
+        r.finish()?;
+        Ok(unit)
+    }
+}
+
+impl Module {
+    /// Encodes every unit of the module in the binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(&MODULE_MAGIC);
+        w.u32(FORMAT_VERSION);
+        w.len(self.units.len());
+        for unit in self.units.iter() {
+            w.unit(unit);
+        }
+        w.0
+    }
+    /// Decodes a module written by `Module::to_bytes`,
+    /// calls also have to refer to units of the module.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Module, DecodeError> {
+        let mut r = Reader::new(bytes, MODULE_MAGIC)?;
+        let len = r.u32()?;
+        let mut units = KeyVec::new();
+        for _ in 0..len {
+            units.push(r.unit()?);
+        }
+        r.finish()?;
+        for unit in units.iter() {
+            for inst in unit.instructions.iter() {
+                if let InstKind::Call(UnitId(u), _) = inst.kind {
+                    check("unit", u, units.len())?;
+                }
+            }
+        }
+        Ok(Module { units })
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+    fn u32(&mut self, n: u32) {
+        self.0.extend_from_slice(&n.to_le_bytes());
+    }
+    fn len(&mut self, n: usize) {
+        self.u32(n as u32);
+    }
+    fn ty(&mut self, t: Type) {
+        self.u8(match t {
+            Type::Int32 => 0,
+            Type::Void => 1,
+        });
+    }
+    fn range(&mut self, [a, b]: [DataPart; 2]) {
+        self.u32(a.0);
+        self.u32(b.0);
+    }
+    fn unit(&mut self, unit: &Unit) {
+        self.u8(unit.settings.volatile as u8);
+        self.u8(match unit.settings.opt_level {
+            OptLevel::O0 => 0,
+            OptLevel::O1 => 1,
+            OptLevel::O2 => 2,
+        });
+        match unit.retsig {
+            Some(t) => self.ty(t),
+            None => self.u8(u8::MAX),
+        }
+        self.len(unit.signatures.len());
+        for t in unit.signatures.iter() {
+            self.ty(*t);
+        }
+        self.len(unit.data.len());
+        for i in unit.data.iter() {
+            self.u32(i.0);
+        }
+        self.len(unit.instructions.len());
+        for inst in unit.instructions.iter() {
+            self.u32(inst.block.0);
+            self.ty(inst.typing);
+            self.kind(&inst.kind);
+            match inst.loc {
+                Some(loc) => {
+                    self.u8(1);
+                    self.u32(loc.file);
+                    self.u32(loc.line);
+                    self.u32(loc.column);
+                }
+                None => self.u8(0),
+            }
+        }
+        self.len(unit.blocks.len());
+        for block in unit.blocks.iter() {
+            self.u32(block.signature[0].0);
+            self.u32(block.signature[1].0);
+            self.len(block.insts.len());
+            for i in block.insts.iter() {
+                self.u32(i.0);
+            }
+        }
+    }
+    fn kind(&mut self, kind: &InstKind) {
+        let binary = |w: &mut Self, tag, [a, b]: [Instruction; 2]| {
+            w.u8(tag);
+            w.u32(a.0);
+            w.u32(b.0);
+        };
+        match *kind {
+            InstKind::Tombstone => self.u8(0),
+            InstKind::FetchArg(n) => {
+                self.u8(1);
+                self.len(n);
+            }
+            InstKind::IConst(n) => {
+                self.u8(2);
+                self.0.extend_from_slice(&(n as i64).to_le_bytes());
+            }
+            InstKind::Add(a) => binary(self, 3, a),
+            InstKind::Sub(a) => binary(self, 4, a),
+            InstKind::Mul(a) => binary(self, 5, a),
+            InstKind::Shl(a) => binary(self, 6, a),
+            InstKind::Less(a) => binary(self, 7, a),
+            InstKind::More(a) => binary(self, 8, a),
+            InstKind::Recur(d) => {
+                self.u8(9);
+                self.range(d);
+            }
+            InstKind::Call(u, d) => {
+                self.u8(10);
+                self.u32(u.0);
+                self.range(d);
+            }
+            InstKind::Terminator(TermData::DoIf(c)) => {
+                self.u8(11);
+                self.u32(c.0);
+            }
+            InstKind::Terminator(TermData::Branch(b, d)) => {
+                self.u8(12);
+                self.u32(b.0);
+                self.range(d);
+            }
+        }
+    }
+}
+
+fn check(what: &'static str, index: u32, len: usize) -> Result<(), DecodeError> {
+    if (index as usize) < len {
+        Ok(())
+    } else {
+        Err(DecodeError::OutOfRange { what, index, len })
+    }
+}
+// a [start, end) range, which may be empty at the end
+fn check_range(what: &'static str, [a, b]: [u32; 2], len: usize) -> Result<(), DecodeError> {
+    if a > b {
+        return Err(DecodeError::OutOfRange {
+            what,
+            index: a,
+            len: b as usize,
+        });
+    }
+    if b as usize > len {
+        return Err(DecodeError::OutOfRange {
+            what,
+            index: b,
+            len,
+        });
+    }
+    Ok(())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], magic: [u8; 4]) -> Result<Self, DecodeError> {
+        let mut r = Self(bytes);
+        if r.take(4).ok() != Some(&magic[..]) {
+            return Err(DecodeError::Magic);
+        }
+        match r.u32()? {
+            FORMAT_VERSION => Ok(r),
+            v => Err(DecodeError::Version(v)),
+        }
+    }
+    fn finish(&self) -> Result<(), DecodeError> {
+        match self.0.len() {
+            0 => Ok(()),
+            n => Err(DecodeError::TrailingBytes(n)),
+        }
+    }
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.0.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    // a section length, bounded by the input left so
+    // that a corrupt length can't allocate arbitrarily
+    fn len(&mut self, min_size: usize) -> Result<usize, DecodeError> {
+        let len = self.u32()? as usize;
+        if len.saturating_mul(min_size) > self.0.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        Ok(len)
+    }
+    fn ty(&mut self) -> Result<Type, DecodeError> {
+        match self.u8()? {
+            0 => Ok(Type::Int32),
+            1 => Ok(Type::Void),
+            tag => Err(DecodeError::Tag { what: "type", tag }),
+        }
+    }
+    fn range(&mut self) -> Result<[u32; 2], DecodeError> {
+        Ok([self.u32()?, self.u32()?])
+    }
+    fn unit(&mut self) -> Result<Unit, DecodeError> {
+        let mut unit = Unit::new();
+        unit.settings = Settings {
+            volatile: self.u8()? != 0,
+            opt_level: match self.u8()? {
+                0 => OptLevel::O0,
+                1 => OptLevel::O1,
+                2 => OptLevel::O2,
+                tag => {
+                    return Err(DecodeError::Tag {
+                        what: "opt level",
+                        tag,
+                    })
+                }
+            },
+        };
+        unit.retsig = match self.u8()? {
+            u8::MAX => None,
+            0 => Some(Type::Int32),
+            1 => Some(Type::Void),
+            tag => return Err(DecodeError::Tag { what: "type", tag }),
+        };
+        let len = self.len(1)?;
+        unit.signatures = (0..len).map(|_| self.ty()).collect::<Result<_, _>>()?;
+        let len = self.len(4)?;
+        unit.data = (0..len)
+            .map(|_| self.u32().map(Instruction))
+            .collect::<Result<_, _>>()?;
+        let len = self.len(7)?;
+        unit.instructions = (0..len)
+            .map(|_| {
+                Ok(InstData {
+                    block: Block(self.u32()?),
+                    typing: self.ty()?,
+                    kind: self.kind()?,
+                    loc: self.loc()?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let len = self.len(12)?;
+        unit.blocks = (0..len)
+            .map(|_| {
+                let sig = self.range()?;
+                let len = self.len(4)?;
+                let insts = (0..len)
+                    .map(|_| self.u32().map(Instruction))
+                    .collect::<Result<_, _>>()?;
+                Ok(BlockData {
+                    signature: sig.map(SignaturePart),
+                    insts,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        unit.restore()?;
+        Ok(unit)
+    }
+    fn loc(&mut self) -> Result<Option<SourceLoc>, DecodeError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(SourceLoc {
+                file: self.u32()?,
+                line: self.u32()?,
+                column: self.u32()?,
+            })),
+            tag => Err(DecodeError::Tag {
+                what: "location",
+                tag,
+            }),
+        }
+    }
+    fn kind(&mut self) -> Result<InstKind, DecodeError> {
+        let data = |r: &mut Self| r.range().map(|d| d.map(DataPart));
+        let insts = |r: &mut Self| Ok([Instruction(r.u32()?), Instruction(r.u32()?)]);
+        Ok(match self.u8()? {
+            0 => InstKind::Tombstone,
+            1 => InstKind::FetchArg(self.u32()? as usize),
+            2 => {
+                let n = i64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                InstKind::IConst(n as isize)
+            }
+            3 => InstKind::Add(insts(self)?),
+            4 => InstKind::Sub(insts(self)?),
+            5 => InstKind::Mul(insts(self)?),
+            6 => InstKind::Shl(insts(self)?),
+            7 => InstKind::Less(insts(self)?),
+            8 => InstKind::More(insts(self)?),
+            9 => InstKind::Recur(data(self)?),
+            10 => InstKind::Call(UnitId(self.u32()?), data(self)?),
+            11 => InstKind::Terminator(TermData::DoIf(Instruction(self.u32()?))),
+            12 => InstKind::Terminator(TermData::Branch(Block(self.u32()?), data(self)?)),
+            tag => {
+                return Err(DecodeError::Tag {
+                    what: "instruction",
+                    tag,
+                })
+            }
+        })
+    }
+}
+
+impl Unit {
+    // rebuilds what isn't stored of a unit read from
+    // somewhere, once it is known to be valid
+    pub(crate) fn restore(&mut self) -> Result<(), DecodeError> {
+        validate(self)?;
+        if self.retsig.is_some() {
+            self.validate_finalized()?;
+        }
+        self.uses = crate::uses::Uses::compute(self);
+        if self.retsig.is_some() {
+            self.update_liveness();
+        }
+        Ok(())
+    }
+    // finalized units pass the checks of `finalize`, which
+    // the passes and the interpreter rely upon
+    fn validate_finalized(&mut self) -> Result<(), DecodeError> {
+        check("block", 0, self.blocks.len())?;
+        if let Some(b) = self.unterminated() {
+            return Err(DecodeError::Unterminated(b));
+        }
+        self.type_check().map_err(DecodeError::Type)?;
+        match self.undefined_use() {
+            Some((inst, operand)) => Err(DecodeError::UndefinedUse { inst, operand }),
+            None => Ok(()),
+        }
+    }
+}
+
+// every index has to point into its section, the
+// tombstones of removed blocks belong to `Block::MAX`
+fn validate(unit: &Unit) -> Result<(), DecodeError> {
+    let insts = unit.instructions.len();
+    let blocks = unit.blocks.len();
+    for i in unit.data.iter() {
+        check("instruction", i.0, insts)?;
+    }
+    for block in unit.blocks.iter() {
+        check_range(
+            "signature",
+            block.signature.map(|s| s.0),
+            unit.signatures.len(),
+        )?;
+        for i in block.insts.iter() {
+            check("instruction", i.0, insts)?;
+        }
+    }
+    for inst in unit.instructions.iter() {
+        if !(inst.block == Block::MAX && matches!(inst.kind, InstKind::Tombstone)) {
+            check("block", inst.block.0, blocks)?;
+        }
+        match inst.kind {
+            InstKind::Recur(d) | InstKind::Call(_, d) => {
+                check_range("data", d.map(|d| d.0), unit.data.len())?;
+            }
+            InstKind::Terminator(TermData::Branch(b, d)) => {
+                if b != Block::MAX {
+                    check("block", b.0, blocks)?;
+                }
+                check_range("data", d.map(|d| d.0), unit.data.len())?;
+            }
+            InstKind::Terminator(TermData::DoIf(c)) => check("instruction", c.0, insts)?,
+            ref kind => {
+                for i in kind.get_insts(&unit.data) {
+                    check("instruction", i.0, insts)?;
+                }
+            }
+        }
+    }
+    // the passes rely on every instruction but a tombstone
+    // being listed once, in the block it belongs to
+    let mut listed = vec![false; insts];
+    for (b, block) in unit.blocks.iter().enumerate() {
+        for &i in block.insts.iter() {
+            let inst = &unit.instructions[i];
+            if matches!(inst.kind, InstKind::Tombstone) {
+                continue;
+            }
+            if inst.block != Block(b as u32) || std::mem::replace(&mut listed[i.0 as usize], true) {
+                return Err(DecodeError::Misplaced(i));
+            }
+        }
+    }
+    for (i, inst) in unit.instructions.iter().enumerate() {
+        if !listed[i] && !matches!(inst.kind, InstKind::Tombstone) {
+            return Err(DecodeError::Misplaced(Instruction(i as u32)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,272 @@
+use crate::data::{Block, InstKind, Instruction, SourceLoc, TermData, Type, Unit, UnitId};
+
+// Type checking over the whole unit
+//   Builder methods only guess the type of their result,
+//   this pass resolves the actual types and then checks
+//   every instruction against them, collecting diagnostics
+
+/// A type error, located at the instruction that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeError {
+    pub block: Block,
+    pub inst: Instruction,
+    /// The source location of the instruction, if it has one.
+    pub loc: Option<SourceLoc>,
+    pub kind: TypeErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeErrorKind {
+    /// An operand refers to an instruction that does not exist.
+    UnknownOperand(Instruction),
+    /// An operand does not produce a value.
+    VoidOperand(Instruction),
+    /// The operands of an instruction disagree on their type.
+    OperandMismatch(Type, Type),
+    /// The condition of an if is not an integer.
+    Condition(Type),
+    /// A constant does not fit into its type.
+    ConstRange(isize, Type),
+    /// `fetchArg` refers past the end of the block signature.
+    ArgIndex { index: usize, len: usize },
+    /// A call refers to a unit that does not exist.
+    UnknownUnit(UnitId),
+    /// The result of a call disagrees with the return type of its callee.
+    CallResult { expected: Type, found: Type },
+    /// A branch refers to a block that does not exist.
+    UnknownBlock(Block),
+    /// A branch, recursion or return passes the wrong amount of arguments.
+    ArgCount { expected: usize, found: usize },
+    /// A branch, recursion or return passes an argument of the wrong type.
+    ArgType {
+        index: usize,
+        expected: Type,
+        found: Type,
+    },
+}
+
+impl Unit {
+    /// Resolves the type of every instruction and checks
+    /// that operands agree with each other and with the
+    /// signatures they are passed to.
+    pub fn type_check(&mut self) -> Result<(), Vec<TypeError>> {
+        self.resolve_types();
+        let mut errors = Vec::new();
+        for i in (0..self.instructions.len()).map(|i| Instruction(i as u32)) {
+            self.check_inst(i, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+    // recursion only learns its type once the return signature
+    // is known, so types are propagated until nothing changes
+    fn resolve_types(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in (0..self.instructions.len()).map(|i| Instruction(i as u32)) {
+                let t = self.infer_type(&self.instructions[i].kind, self.instructions[i].block);
+                if let Some(t) = t {
+                    if self.instructions[i].typing != t {
+                        self.instructions[i].typing = t;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    // returns None if the declared type is to be kept
+    pub(crate) fn infer_type(&self, kind: &InstKind, block: Block) -> Option<Type> {
+        Some(match kind {
+            InstKind::IConst(_) => return None,
+            InstKind::FetchArg(i) => {
+                let sig = self.blocks.get(block)?.signature;
+                self.signatures[sig].get(*i).copied().unwrap_or(Type::Void)
+            }
+            InstKind::Add([a, _])
+            | InstKind::Sub([a, _])
+            | InstKind::Mul([a, _])
+            | InstKind::Shl([a, _]) => self.instructions.get(*a)?.typing,
+            InstKind::Less(_) | InstKind::More(_) => Type::Int32,
+            // unknown until the return signature is known
+            InstKind::Recur(_) => return self.retsig,
+            // the callee is only known to the module
+            InstKind::Call(..) => return None,
+            InstKind::Tombstone | InstKind::Terminator(_) => Type::Void,
+        })
+    }
+    fn check_inst(&self, i: Instruction, errors: &mut Vec<TypeError>) {
+        let inst = &self.instructions[i];
+        let mut error = |kind| {
+            errors.push(TypeError {
+                block: inst.block,
+                inst: i,
+                loc: inst.loc,
+                kind,
+            })
+        };
+        match &inst.kind {
+            InstKind::Tombstone => {}
+            InstKind::FetchArg(index) => {
+                let len = self.signatures[self.blocks[inst.block].signature].len();
+                if *index >= len {
+                    error(TypeErrorKind::ArgIndex { index: *index, len });
+                }
+            }
+            InstKind::IConst(n) => {
+                let fits = match inst.typing {
+                    Type::Int32 => i32::try_from(*n).is_ok(),
+                    Type::Void => false,
+                };
+                if !fits {
+                    error(TypeErrorKind::ConstRange(*n, inst.typing));
+                }
+            }
+            InstKind::Add([a, b])
+            | InstKind::Sub([a, b])
+            | InstKind::Mul([a, b])
+            | InstKind::Shl([a, b])
+            | InstKind::Less([a, b])
+            | InstKind::More([a, b]) => {
+                let lhs = self.operand_type(*a, &mut error);
+                let rhs = self.operand_type(*b, &mut error);
+                if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+                    if lhs != rhs {
+                        error(TypeErrorKind::OperandMismatch(lhs, rhs));
+                    }
+                }
+            }
+            InstKind::Recur(d) => {
+                let sig = self
+                    .blocks
+                    .get(Block(0))
+                    .map(|b| &self.signatures[b.signature]);
+                self.check_args(&self.data[*d], sig.unwrap_or(&[]), &mut error);
+            }
+            InstKind::Call(_, d) => {
+                for arg in self.data[*d].iter() {
+                    self.operand_type(*arg, &mut error);
+                }
+            }
+            InstKind::Terminator(TermData::DoIf(c)) => match self.operand_type(*c, &mut error) {
+                Some(Type::Int32) | None => {}
+                Some(t) => error(TypeErrorKind::Condition(t)),
+            },
+            InstKind::Terminator(TermData::Branch(Block::MAX, d)) => {
+                // the return signature is only known once finalized
+                let Some(ret) = self.retsig else { return };
+                let sig: &[Type] = match ret {
+                    Type::Void => &[],
+                    _ => std::slice::from_ref(&ret),
+                };
+                self.check_args(&self.data[*d], sig, &mut error);
+            }
+            InstKind::Terminator(TermData::Branch(b, d)) => match self.blocks.get(*b) {
+                Some(target) => {
+                    let sig = &self.signatures[target.signature];
+                    self.check_args(&self.data[*d], sig, &mut error);
+                }
+                None => error(TypeErrorKind::UnknownBlock(*b)),
+            },
+        }
+    }
+    fn operand_type<F>(&self, op: Instruction, error: &mut F) -> Option<Type>
+    where
+        F: FnMut(TypeErrorKind),
+    {
+        match self.instructions.get(op) {
+            None => {
+                error(TypeErrorKind::UnknownOperand(op));
+                None
+            }
+            Some(inst) if inst.typing == Type::Void => {
+                if !self.unknown_type(op) {
+                    error(TypeErrorKind::VoidOperand(op));
+                }
+                None
+            }
+            Some(inst) => Some(inst.typing),
+        }
+    }
+    // before finalizing, recursion and the arithmetic on
+    // its result have no type yet rather than none at all
+    fn unknown_type(&self, mut i: Instruction) -> bool {
+        if self.retsig.is_some() {
+            return false;
+        }
+        // only broken units have operands going in circles
+        for _ in 0..self.instructions.len() {
+            match self.instructions.get(i).map(|inst| &inst.kind) {
+                Some(InstKind::Recur(_)) => return true,
+                Some(
+                    InstKind::Add([a, _])
+                    | InstKind::Sub([a, _])
+                    | InstKind::Mul([a, _])
+                    | InstKind::Shl([a, _]),
+                ) => i = *a,
+                _ => return false,
+            }
+        }
+        false
+    }
+    pub(crate) fn check_args<F>(&self, args: &[Instruction], sig: &[Type], error: &mut F)
+    where
+        F: FnMut(TypeErrorKind),
+    {
+        if args.len() != sig.len() {
+            error(TypeErrorKind::ArgCount {
+                expected: sig.len(),
+                found: args.len(),
+            });
+        }
+        for (index, (arg, expected)) in args.iter().zip(sig).enumerate() {
+            match self.operand_type(*arg, error) {
+                Some(found) if found != *expected => error(TypeErrorKind::ArgType {
+                    index,
+                    expected: *expected,
+                    found,
+                }),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} in {}", self.inst, self.block)?;
+        if let Some(loc) = self.loc {
+            write!(f, " at {loc}")?;
+        }
+        write!(f, ": ")?;
+        match &self.kind {
+            TypeErrorKind::UnknownOperand(i) => write!(f, "operand {i} does not exist"),
+            TypeErrorKind::VoidOperand(i) => write!(f, "operand {i} has no value"),
+            TypeErrorKind::OperandMismatch(a, b) => {
+                write!(f, "operands disagree on type ({a:?} and {b:?})")
+            }
+            TypeErrorKind::Condition(t) => write!(f, "condition is {t:?}, not an integer"),
+            TypeErrorKind::ConstRange(n, t) => write!(f, "constant {n} does not fit {t:?}"),
+            TypeErrorKind::ArgIndex { index, len } => {
+                write!(f, "argument [{index}] out of range for {len} parameters")
+            }
+            TypeErrorKind::UnknownUnit(u) => write!(f, "unit {u} does not exist"),
+            TypeErrorKind::CallResult { expected, found } => {
+                write!(f, "call returns {found:?}, callee returns {expected:?}")
+            }
+            TypeErrorKind::UnknownBlock(b) => write!(f, "block {b} does not exist"),
+            TypeErrorKind::ArgCount { expected, found } => {
+                write!(f, "expected {expected} arguments, found {found}")
+            }
+            TypeErrorKind::ArgType {
+                index,
+                expected,
+                found,
+            } => write!(f, "argument [{index}] is {found:?}, expected {expected:?}"),
+        }
+    }
+}
+impl std::error::Error for TypeError {}
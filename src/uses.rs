@@ -0,0 +1,78 @@
+use crate::data::{DataPart, InstKind, Instruction, Map, Set, Unit};
+use crate::util::KeyVec;
+
+// Def-use chains
+//   Maps each instruction to the places it is used at,
+//   this is maintained by the builder and the mutation
+//   api, so passes never have to rescan the unit
+
+/// A single use of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Use {
+    /// The instruction using the value.
+    pub user: Instruction,
+    /// The position of the value among the operands of the user.
+    pub operand: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Uses(Map<Instruction, Set<Use>>);
+
+impl Uses {
+    /// Recomputes the uses of every instruction from scratch.
+    pub fn compute(unit: &Unit) -> Self {
+        let mut out = Self::default();
+        for (i, inst) in unit.instructions.iter().enumerate() {
+            out.add(Instruction(i as u32), &inst.kind, &unit.data);
+        }
+        out
+    }
+    /// Every use of the given instruction.
+    pub fn of(&self, inst: Instruction) -> impl Iterator<Item = Use> + '_ {
+        self.0.get(&inst).into_iter().flatten().copied()
+    }
+    pub fn is_used(&self, inst: Instruction) -> bool {
+        self.0.get(&inst).is_some_and(|s| !s.is_empty())
+    }
+    pub(crate) fn add(
+        &mut self,
+        user: Instruction,
+        kind: &InstKind,
+        data: &KeyVec<DataPart, Instruction>,
+    ) {
+        for (operand, op) in kind.get_insts(data).iter().enumerate() {
+            self.0.entry(*op).or_default().insert(Use { user, operand });
+        }
+    }
+    pub(crate) fn remove(
+        &mut self,
+        user: Instruction,
+        kind: &InstKind,
+        data: &KeyVec<DataPart, Instruction>,
+    ) {
+        for (operand, op) in kind.get_insts(data).iter().enumerate() {
+            if let Some(uses) = self.0.get_mut(op) {
+                uses.remove(&Use { user, operand });
+                if uses.is_empty() {
+                    self.0.remove(op);
+                }
+            }
+        }
+    }
+    // moves all uses of `old` over to `new`
+    pub(crate) fn take(&mut self, old: Instruction) -> Set<Use> {
+        self.0.remove(&old).unwrap_or_default()
+    }
+    pub(crate) fn extend(&mut self, new: Instruction, uses: Set<Use>) {
+        if !uses.is_empty() {
+            self.0.entry(new).or_default().extend(uses);
+        }
+    }
+}
+
+impl Unit {
+    /// The def-use chains of the unit.
+    pub fn uses(&self) -> &Uses {
+        &self.uses
+    }
+}
@@ -16,6 +16,8 @@ pub trait Key {
         Self: Sized;
     fn into(self) -> usize;
 }
+// keys of ranges, not used by any container yet
+#[allow(dead_code)]
 pub trait KeyChain {
     fn from(idx: usize, len: usize) -> Option<Self>
     where
@@ -33,6 +35,9 @@ impl<K, T> KeyVec<K, T> {
     pub fn iter(&self) -> std::slice::Iter<'_, T> {
         self.0.iter()
     }
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.0.iter_mut()
+    }
 }
 impl<K: Key, T> KeyVec<K, T> {
     pub fn push(&mut self, elem: T) -> K {
@@ -43,7 +48,9 @@ impl<K: Key, T> KeyVec<K, T> {
         k
     }
     pub fn push_slice(&mut self, elem: &[T]) -> [K; 2]
-    where T: Clone {
+    where
+        T: Clone,
+    {
         let start = self.next_idx();
         self.0.extend_from_slice(elem);
         let end = self.next_idx();
@@ -55,6 +62,27 @@ impl<K: Key, T> KeyVec<K, T> {
     pub fn get(&self, index: K) -> Option<&T> {
         self.0.get(index.into())
     }
+    pub fn get_mut(&mut self, index: K) -> Option<&mut T> {
+        self.0.get_mut(index.into())
+    }
+}
+impl<K, T> std::default::Default for KeyVec<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K, T> std::iter::FromIterator<T> for KeyVec<K, T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self(iter.into_iter().collect(), PhantomData)
+    }
+}
+impl<K, T> std::iter::IntoIterator for KeyVec<K, T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
 }
 impl<K: Key, T> std::ops::Index<K> for KeyVec<K, T> {
     type Output = T;
@@ -1,207 +1,244 @@
-use crate::data::{Block, BlockData, InstData, InstKind, Instruction, Set, Type, Unit, LiveData, DataPart, Map};
+use crate::data::{
+    Block, BlockData, DataPart, InstKind, Instruction, LiveData, Map, Set, TermData, Unit,
+};
 use crate::util::KeyVec;
 
 impl Unit {
-    fn assign_vregs(&mut self) -> KeyVec<Instruction, u32> {
-        let mut out = KeyVec::new();
-        // save fixup operations for later (as inserting them into
-        // the instruction pool would mess up all of the indeces)
-        let mut fixups = Map::<Instruction, (u32, Instruction)>::new();
-        let mut current_reg = 0;
-        for i in (0..self.instructions.len()).map(|i| Instruction(i as u32)) {
-            let instdata = &self.instructions[i];
-            if instdata.kind.is_reuse() { // if instdata.is_reuse()
-                let lhs = instdata.kind.get_insts(&self.data)[0];
-                if self.liveness.get(&(instdata.block, lhs)).unwrap() != &LiveData::Partial(i) {
-                    fixups.insert(i, (current_reg, lhs));
-                    out[i] = current_reg;
-                } else {
-                    out[i] = out[lhs];
-                }
-            } else {
-                out[i] = current_reg;
+    // the terminating instructions of a block,
+    // either a single branch or an if and two branches
+    pub(crate) fn terminator(&self, block: Block) -> &[Instruction] {
+        let blockdata = &self.blocks[block];
+        &blockdata.insts[blockdata.term_start(&self.instructions)..]
+    }
+    /// The blocks a block branches to, in order of the branches.
+    pub fn successors(&self, block: Block) -> Vec<Block> {
+        let mut out = Vec::new();
+        for n in self.blocks[block]
+            .get_next(self)
+            .into_iter()
+            .rev()
+            .flatten()
+        {
+            if !out.contains(&n) {
+                out.push(n);
             }
-            current_reg += 1;
         }
         out
     }
-    fn depth_first<F, A>(&mut self, mut callback: F) -> A
-    where F: FnMut(Vec<A>, &mut Self, Block) -> A
-    {
-        let mut vis = Vec::new();
-        self.depth_first_internal(&mut vis, Block(0), &mut callback)
-    }
-    fn depth_first_internal<F, A>(&mut self, vis: &mut Vec<Block>, block: Block, callback: &mut F) -> A
-    where F: FnMut(Vec<A>, &mut Self, Block) -> A
-    {
-        let scope = vis.len();
-        vis.push(block);
-        let blockdata = &self.blocks[block];
-        let mut acc = Vec::new();
-        for c in blockdata.get_next(self).into_iter().filter_map(|t| t) {
-			acc.push(self.depth_first_internal(vis, c, callback));
+    pub(crate) fn predecessors(&self) -> Map<Block, Vec<Block>> {
+        let mut out = Map::<Block, Vec<Block>>::new();
+        for (b, blockdata) in self.blocks.iter().enumerate() {
+            for n in blockdata.get_next(self).into_iter().flatten() {
+                out.entry(n).or_default().push(Block(b as u32));
+            }
         }
-        vis.truncate(scope);
-        callback(acc, self, block)
-    }
-    pub(crate) fn annotate_liveness(&mut self) {
-        // do a depth-first-traversal of the cfg
-        // and determine liveness of each used instruction
-		self.depth_first(|acc: Vec<Set<Instruction>>, unit, block| {
-    		let mut set = Set::new();
-    		for mut s in acc.into_iter() {
-				for i in s.iter() {
-					unit.liveness.insert((block, *i), LiveData::Alive);
-				}
-				set.append(&mut s);
-    		}
-    	    let blockdata = &unit.blocks[block];
-    	    let first = blockdata.inst_range[0].0;
-    	    let last = blockdata.inst_range[1].0;
-    	    for i in (first..last).rev().map(|i| Instruction(i)) {
-				set.remove(&i);
-				let used = unit.instructions[i].kind.get_insts(&unit.data);
-				for u in used.into_iter() {
-					if !unit.liveness.contains_key(&(block, *u)) {
-						unit.liveness.insert((block, *u), LiveData::Partial(i));
-						set.insert(*u);
-					}
-				}
-    	    }
-    	    set
-		});
-    }
-    pub(crate) fn check_dependencies(&mut self) {
-		let set = self.depth_first(|acc, unit, block| {
-    		let mut set = Set::new();
-			let blockdata = &unit.blocks[block];
-			let first = blockdata.inst_range[0].0;
-			let last = blockdata.inst_range[1].0;
-        	// determine indirect dependency
-        	acc.into_iter().for_each(|mut s| set.append(&mut s));
-        	// determine direct dependency
-        	// (use of instructions directly by this block,
-        	//  including self)
-        	for i in (first..last).map(|i| Instruction(i)) {
-            	let inst = &unit.instructions[i];
-            	set.insert(inst.block);
-        	}
-        	// satisfy dependency on self
-        	set.remove(&block);
-        	set
-		});
-        // if any dependency bubbled up to block 0, then
-        // there must be a codepath that does not fulfill
-        // the dependency, aka a reqd. block is not a dominator
-		if !set.is_empty() { panic!("aaaa"); }
+        out
     }
-    fn width_first_traversal<F>(&mut self, mut callback: F)
-    where
-        F: FnMut(&mut Self, Block),
-    {
-        let mut order = vec![Block(0)];
-        let mut i = 0;
-        while let Some(block) = order.get(i).cloned() {
-            let [a, b] = self.blocks[block].get_next(self);
-            a.map(|a| {
-                if !order.contains(&a) {
-                    order.push(a)
+    /// Numbers the values of a finalized unit with virtual
+    /// registers, as taken by `Unit::lifetime_table_with`.
+    /// Arithmetic overwrites the register of its left operand
+    /// if that dies there, otherwise it needs a copy first.
+    pub fn assign_vregs(&self) -> Map<Instruction, u32> {
+        let mut out = Map::new();
+        let mut current_reg = 0;
+        for blockdata in self.blocks.iter() {
+            for &i in blockdata.insts.iter() {
+                let instdata = &self.instructions[i];
+                if matches!(instdata.kind, InstKind::Tombstone) || instdata.kind.is_term() {
+                    continue;
                 }
-            });
-            b.map(|b| {
-                if !order.contains(&b) {
-                    order.push(b)
+                if instdata.kind.is_reuse() {
+                    let lhs = instdata.kind.get_insts(&self.data)[0];
+                    let dies =
+                        self.liveness.get(&(instdata.block, lhs)) == Some(&LiveData::Partial(i));
+                    if let (true, Some(reg)) = (dies, out.get(&lhs)) {
+                        out.insert(i, *reg);
+                        continue;
+                    }
                 }
-            });
-            callback(self, block);
-            i += 1;
+                out.insert(i, current_reg);
+                current_reg += 1;
+            }
         }
+        out
     }
-    pub(crate) fn remove_unused(&mut self) {
-        let mut unused = vec![true; self.instructions.len()];
-        self.width_first_traversal(|unit, block| {
-            let blockdata = &unit.blocks[block];
-            let first = blockdata.inst_range[0].0;
-            let last = blockdata.inst_range[1].0;
-            for inst in (first..last).rev() {
-                let instruction = &unit.instructions[Instruction(inst)];
-                if instruction.kind.is_term() {
-                    unused[inst as usize] = false;
+    pub(crate) fn annotate_liveness(&mut self) {
+        // walk up the cfg from every use of a value until its
+        // definition is reached, the value is alive at the end
+        // of every block passed on the way
+        let preds = self.predecessors();
+        let mut alive = Set::new();
+        for i in (0..self.instructions.len()).map(|i| Instruction(i as u32)) {
+            let def = self.instructions[i].block;
+            let mut seen = Set::new();
+            let mut work: Vec<Block> = self
+                .uses
+                .of(i)
+                .map(|u| self.instructions[u.user].block)
+                .filter(|b| *b != def && seen.insert(*b))
+                .collect();
+            while let Some(b) = work.pop() {
+                for p in preds.get(&b).into_iter().flatten() {
+                    alive.insert((*p, i));
+                    if *p != def && seen.insert(*p) {
+                        work.push(*p);
+                    }
                 }
-                if unused[inst as usize] {
-                    continue;
+            }
+        }
+        self.liveness = alive.into_iter().map(|k| (k, LiveData::Alive)).collect();
+        // values not alive past a block die at their last use in it
+        for (b, blockdata) in self.blocks.iter().enumerate() {
+            let block = Block(b as u32);
+            for &i in blockdata.insts.iter().rev() {
+                for u in self.instructions[i].kind.get_insts(&self.data) {
+                    self.liveness
+                        .entry((block, *u))
+                        .or_insert(LiveData::Partial(i));
                 }
-                let refs = instruction.kind.get_insts(&unit.data);
-                for i in refs {
-                    unused[i.0 as usize] = false;
+            }
+        }
+    }
+    pub(crate) fn check_dependencies(&mut self) {
+        if let Some((i, u)) = self.undefined_use() {
+            let at = match self.instructions[i].loc {
+                Some(loc) => format!(" at {loc}"),
+                None => String::new(),
+            };
+            panic!(
+                "Instruction {}{} uses {} which is not always defined!",
+                i, at, u
+            );
+        }
+    }
+    // the first block not ending in a branch or in an if and
+    // two branches, with nothing but values in front of them
+    pub(crate) fn unterminated(&self) -> Option<Block> {
+        (0..self.blocks.len() as u32).map(Block).find(|b| {
+            let blockdata = &self.blocks[*b];
+            let term: Vec<&InstKind> = blockdata.insts[blockdata.term_start(&self.instructions)..]
+                .iter()
+                .map(|i| &self.instructions[*i].kind)
+                .filter(|k| !matches!(k, InstKind::Tombstone))
+                .collect();
+            !matches!(
+                term[..],
+                [InstKind::Terminator(TermData::Branch(..))]
+                    | [
+                        InstKind::Terminator(TermData::DoIf(_)),
+                        InstKind::Terminator(TermData::Branch(..)),
+                        InstKind::Terminator(TermData::Branch(..))
+                    ]
+            )
+        })
+    }
+    /// The first instruction along with an operand that is not
+    /// defined on every path leading to it, which `finalize`
+    /// panics on. Every block needs to be terminated.
+    pub fn undefined_use(&mut self) -> Option<(Instruction, Instruction)> {
+        // every operand has to be defined on every path leading
+        // to its use, aka the defining block is a dominator
+        // and comes first within the same block
+        self.dominators();
+        let doms = self.analyses.dominators.as_ref().unwrap();
+        for (b, blockdata) in self.blocks.iter().enumerate() {
+            let block = Block(b as u32);
+            if !doms.is_reachable(block) {
+                continue;
+            }
+            let mut seen = Set::new();
+            for &i in blockdata.insts.iter() {
+                for u in self.instructions[i].kind.get_insts(&self.data) {
+                    let def = self.instructions[*u].block;
+                    if !doms.dominates(def, block) || (def == block && !seen.contains(u)) {
+                        return Some((i, *u));
+                    }
                 }
+                seen.insert(i);
             }
-        });
-        for i in unused
-            .into_iter()
-            .enumerate()
-            .filter(|(_, b)| *b)
-            .map(|(i, _)| Instruction(i as u32))
-        {
-            if self.settings.volatile {
+        }
+        None
+    }
+    // the frontend is not expected to emit dead code when volatile
+    // is set, optimizations however may leave plenty behind
+    pub(crate) fn check_volatile(&self) {
+        for (i, inst) in self.instructions.iter().enumerate() {
+            let i = Instruction(i as u32);
+            if !inst.kind.is_term()
+                && !matches!(inst.kind, InstKind::Tombstone)
+                && !self.uses.is_used(i)
+            {
                 panic!("Instruction {} is unused but volatile is set!", i);
             }
-            let inst = InstData {
-                block: self.instructions[i].block,
-                kind: InstKind::Tombstone,
-                typing: Type::Void,
-            };
-            self.instructions[i] = inst;
         }
     }
+    pub(crate) fn remove_unused(&mut self) -> bool {
+        let mut changed = false;
+        // anything but a terminator dies once it has no uses left,
+        // which in turn may leave its operands without uses
+        let mut work: Vec<Instruction> = (0..self.instructions.len())
+            .map(|i| Instruction(i as u32))
+            .collect();
+        while let Some(i) = work.pop() {
+            let kind = &self.instructions[i].kind;
+            if kind.is_term() || matches!(kind, InstKind::Tombstone) || self.uses.is_used(i) {
+                continue;
+            }
+            work.extend_from_slice(kind.get_insts(&self.data));
+            self.tombstone(i);
+            changed = true;
+        }
+        changed
+    }
 }
 
 impl BlockData {
     // returns None for the return block index as it does not count as a block
     fn get_next(&self, unit: &Unit) -> [Option<Block>; 2] {
-        let [inst_start, inst_end] = self.inst_range;
-        let mut out = [
-            unit.instructions
-                .get(inst_end)
-                .map(|i| i.kind.get_block())
-                .flatten(),
-            unit.instructions
-                .get(Instruction(inst_end.0 - 1))
-                .map(|i| i.kind.get_block())
-                .flatten(),
-        ];
-        if inst_end == inst_start {
-            out[1] = None;
+        // an if is followed by two branches, otherwise
+        // the block ends in a single one
+        let mut out = [None; 2];
+        for (o, i) in out.iter_mut().zip(self.insts.iter().rev()) {
+            *o = unit.instructions[*i].kind.get_block();
         }
         out
     }
 }
 
 impl InstKind {
-    fn is_reuse(&self) -> bool {
-        todo!()
-    }
-    fn get_block(&self) -> Option<Block> {
+    pub(crate) fn get_block(&self) -> Option<Block> {
         match self {
             Self::Terminator(crate::data::TermData::Branch(Block::MAX, _)) => None,
             Self::Terminator(crate::data::TermData::Branch(b, _)) => Some(*b),
             _ => None,
         }
     }
-    fn get_insts<'a>(&'a self, data: &'a KeyVec<DataPart, Instruction>) -> &'a [Instruction] {
+    pub(crate) fn get_insts<'a>(
+        &'a self,
+        data: &'a KeyVec<DataPart, Instruction>,
+    ) -> &'a [Instruction] {
         match self {
-            Self::Add(a) | Self::Sub(a) | Self::Less(a) | Self::More(a) => a,
-            Self::Recur(a) | Self::Terminator(crate::data::TermData::Branch(_, a)) => {
-                &data[*a]
-            }
+            Self::Add(a)
+            | Self::Sub(a)
+            | Self::Mul(a)
+            | Self::Shl(a)
+            | Self::Less(a)
+            | Self::More(a) => a,
+            Self::Recur(a)
+            | Self::Call(_, a)
+            | Self::Terminator(crate::data::TermData::Branch(_, a)) => &data[*a],
             Self::Terminator(crate::data::TermData::DoIf(i)) => std::slice::from_ref(i),
             _ => &[],
         }
     }
+    // two address arithmetic writes over its left operand
+    fn is_reuse(&self) -> bool {
+        matches!(
+            self,
+            Self::Add(_) | Self::Sub(_) | Self::Mul(_) | Self::Shl(_)
+        )
+    }
     fn is_term(&self) -> bool {
-        match self {
-            Self::Terminator(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Terminator(_))
     }
 }